@@ -0,0 +1,130 @@
+extern crate rusty16;
+
+use std::{env, fs, process};
+use std::path::Path;
+use std::convert::TryFrom;
+use rusty16::memory::{RomHeader, SPEC_VERSION};
+
+const USAGE: &str = "Usage: rusty16-as [OPTIONS] SOURCE
+
+Options:
+    -o, --output FILE   Output file, defaults to SOURCE with a .c16 extension
+    --start ADDR        Start address stored in the CH16 header, defaults to 0
+    --version X.Y       Spec version stored in the CH16 header, defaults to the newest one
+    --reserved BYTE     Reserved header byte, defaults to 0
+    --raw               Write a raw binary without the CH16 header";
+
+struct Options {
+    source: String,
+    output: Option<String>,
+    start: u16,
+    version: u8,
+    reserved: u8,
+    raw: bool,
+}
+
+impl Options {
+    fn parse() -> Options {
+        let mut source = None;
+        let mut output = None;
+        let mut start = 0;
+        let mut version = SPEC_VERSION;
+        let mut reserved = 0;
+        let mut raw = false;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" | "--output" => output = Some(args.next().unwrap_or_else(|| usage(&format!("{} requires a file", arg)))),
+                "--start" => {
+                    let addr = args.next().unwrap_or_else(|| usage("--start requires an address"));
+                    start = parse_addr(&addr).unwrap_or_else(|| usage(&format!("Invalid start address: {}", addr)));
+                },
+                "--version" => {
+                    let text = args.next().unwrap_or_else(|| usage("--version requires a version"));
+                    version = parse_version(&text).unwrap_or_else(|| usage(&format!("Invalid version: {}", text)));
+                },
+                "--reserved" => {
+                    let byte = args.next().unwrap_or_else(|| usage("--reserved requires a byte"));
+                    reserved = parse_addr(&byte).and_then(|b| u8::try_from(b).ok())
+                        .unwrap_or_else(|| usage(&format!("Invalid byte: {}", byte)));
+                },
+                "--raw" => raw = true,
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                _ if arg.starts_with('-') => usage(&format!("Unknown option: {}", arg)),
+                _ => source = Some(arg),
+            }
+        }
+
+        Options {
+            source: source.unwrap_or_else(|| usage("No source file given")),
+            output,
+            start,
+            version,
+            reserved,
+            raw,
+        }
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal address.
+fn parse_addr(text: &str) -> Option<u16> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parses a `MAJOR.MINOR` spec version into the header encoding.
+fn parse_version(text: &str) -> Option<u8> {
+    let (major, minor) = text.split_once('.')?;
+    let (major, minor) = (major.parse::<u8>().ok()?, minor.parse::<u8>().ok()?);
+    if major > 0xf || minor > 0xf {
+        return None;
+    }
+
+    Some(major << 4 | minor)
+}
+
+fn usage(err: &str) -> ! {
+    eprintln!("{}\n\n{}", err, USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let options = Options::parse();
+
+    let rom = match rusty16::asm::assemble_file(&options.source) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    };
+
+    let image = if options.raw {
+        rom
+    } else {
+        let header = RomHeader {
+            version: options.version,
+            reserved: options.reserved,
+            ..RomHeader::new(&rom, options.start)
+        };
+        let mut image = header.to_bytes().to_vec();
+        image.extend_from_slice(&rom);
+        image
+    };
+
+    let source = options.source;
+    let output = options.output.unwrap_or_else(|| {
+        Path::new(&source).with_extension("c16").to_string_lossy().into_owned()
+    });
+
+    if let Err(err) = fs::write(&output, &image) {
+        eprintln!("Can't write {}: {}", output, err);
+        process::exit(1);
+    }
+}
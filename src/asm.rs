@@ -0,0 +1,743 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use enum_primitive::FromPrimitive;
+use crate::error::{Error, Result};
+use crate::memory::MEMORY_SIZE;
+use crate::opcode::{Opcode, JMP_TYPE};
+use crate::cpu::INSTRUCTION_SIZE;
+
+/// Includes nested deeper than this are most likely recursive.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Constants referring to each other deeper than this are most likely recursive.
+const MAX_EVAL_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct Location {
+    path: String,
+    line: usize,
+}
+
+impl Location {
+    fn error(&self, message: String) -> Error {
+        Error::Asm {
+            path: self.path.clone(),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+enum Statement {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+    Binary(Vec<u8>),
+}
+
+/// A statement placed at its address by the first pass.
+struct Line {
+    addr: usize,
+    statement: Statement,
+    location: Location,
+}
+
+enum Symbol {
+    Label(u16),
+    Constant(String),
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Operand {
+    Reg(u8),
+    Sp,
+    Imm(i32),
+}
+
+/// Two pass assembler for Chip16 sources in tchip16 syntax.
+///
+/// Sources are added with `add_file` or `add_source`, which lay out the
+/// statements and collect labels and `equ` constants. `assemble` then
+/// resolves the operands and returns the raw ROM image starting at address 0.
+#[derive(Default)]
+pub struct Assembler {
+    lines: Vec<Line>,
+    symbols: HashMap<String, Symbol>,
+    addr: usize,
+    depth: usize,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler::default()
+    }
+
+    /// Adds a source file. Included files and binaries are looked up relative to it.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        self.add_source(&path.to_string_lossy(), &source, dir)
+    }
+
+    /// Adds source text. `name` is only used in error messages, included files
+    /// and binaries are looked up relative to `dir`.
+    pub fn add_source(&mut self, name: &str, source: &str, dir: &Path) -> Result<()> {
+        for (i, text) in source.lines().enumerate() {
+            let location = Location {
+                path: name.to_string(),
+                line: i + 1,
+            };
+
+            self.parse_line(text, &location, dir)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves all operands and returns the ROM image.
+    pub fn assemble(&self) -> Result<Vec<u8>> {
+        let mut rom = vec![0; self.addr];
+
+        for line in self.lines.iter() {
+            let bytes = self.emit(&line.statement).map_err(|err| line.location.error(err))?;
+            rom[line.addr..line.addr + bytes.len()].copy_from_slice(&bytes);
+        }
+
+        Ok(rom)
+    }
+
+    fn parse_line(&mut self, text: &str, location: &Location, dir: &Path) -> Result<()> {
+        let mut rest = strip_comment(text).trim();
+
+        while let Some(i) = rest.find(':') {
+            let name = rest[..i].trim();
+            if !is_identifier(name) {
+                break;
+            }
+
+            self.define(name, Symbol::Label(self.addr as u16), location)?;
+            rest = rest[i + 1..].trim();
+        }
+
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, args) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], rest[i..].trim()),
+            None => (rest, ""),
+        };
+
+        if let Some(value) = strip_keyword(args, "equ") {
+            if !is_identifier(mnemonic) {
+                return Err(location.error(format!("Invalid constant name: {}", mnemonic)));
+            }
+
+            return self.define(mnemonic, Symbol::Constant(value.to_string()), location);
+        }
+
+        let statement = match mnemonic.to_ascii_lowercase().as_str() {
+            "db" => Statement::Bytes(split_operands(args)),
+            "dw" => Statement::Words(split_operands(args)),
+            "include" => return self.include(&dir.join(unquote(args)), location),
+            "importbin" => self.importbin(args, location, dir)?,
+            mnemonic => Statement::Instruction {
+                mnemonic: mnemonic.to_string(),
+                operands: split_operands(args),
+            },
+        };
+
+        let size = match statement {
+            Statement::Instruction { .. } => INSTRUCTION_SIZE,
+            Statement::Bytes(ref values) => values.iter().map(|value| parse_string(value).map_or(1, |s| s.len())).sum(),
+            Statement::Words(ref values) => values.len() * 2,
+            Statement::Binary(ref data) => data.len(),
+        };
+
+        self.lines.push(Line {
+            addr: self.addr,
+            statement,
+            location: location.clone(),
+        });
+
+        self.addr += size;
+        if self.addr > MEMORY_SIZE {
+            return Err(location.error(String::from("Program doesn't fit into memory")));
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, location: &Location) -> Result<()> {
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(location.error(format!("Duplicate symbol: {}", name)));
+        }
+
+        Ok(())
+    }
+
+    fn include(&mut self, path: &Path, location: &Location) -> Result<()> {
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(location.error(format!("Includes nested too deep: {}", path.display())));
+        }
+
+        let source = fs::read_to_string(path)
+            .map_err(|err| location.error(format!("Can't read {}: {}", path.display(), err)))?;
+        let dir = path.parent().map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+
+        self.depth += 1;
+        let result = self.add_source(&path.to_string_lossy(), &source, &dir);
+        self.depth -= 1;
+
+        result
+    }
+
+    /// `importbin FILE [OFFSET LENGTH] [LABEL]`: inserts a binary file or a slice
+    /// of it, optionally defining a label at its start.
+    fn importbin(&mut self, args: &str, location: &Location, dir: &Path) -> Result<Statement> {
+        let args: Vec<&str> = args.split_whitespace().collect();
+        let (file, range, label) = match args[..] {
+            [file] => (file, None, None),
+            [file, label] => (file, None, Some(label)),
+            [file, offset, length] => (file, Some((offset, length)), None),
+            [file, offset, length, label] => (file, Some((offset, length)), Some(label)),
+            _ => return Err(location.error(String::from("Usage: importbin FILE [OFFSET LENGTH] [LABEL]"))),
+        };
+
+        let path = dir.join(unquote(file));
+        let mut data = fs::read(&path)
+            .map_err(|err| location.error(format!("Can't read {}: {}", path.display(), err)))?;
+
+        if let Some((offset, length)) = range {
+            let offset = self.eval(offset, 0).map_err(|err| location.error(err))? as usize;
+            let length = self.eval(length, 0).map_err(|err| location.error(err))? as usize;
+            if offset.checked_add(length).filter(|end| *end <= data.len()).is_none() {
+                return Err(location.error(format!("{} is only {} bytes long", path.display(), data.len())));
+            }
+
+            data = data[offset..offset + length].to_vec();
+        }
+
+        if let Some(label) = label {
+            self.define(label, Symbol::Label(self.addr as u16), location)?;
+        }
+
+        Ok(Statement::Binary(data))
+    }
+
+    fn emit(&self, statement: &Statement) -> std::result::Result<Vec<u8>, String> {
+        match statement {
+            Statement::Instruction { mnemonic, operands: texts } => {
+                let operands = texts.iter()
+                    .map(|operand| self.operand(operand))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+
+                self.instruction(mnemonic, &operands)
+                    .map(|code| code.to_vec())
+                    .ok_or_else(|| format!("Invalid instruction: {} {}", mnemonic.to_ascii_uppercase(), texts.join(", ")))
+            },
+            Statement::Bytes(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    match parse_string(value) {
+                        Some(s) => bytes.extend_from_slice(&s),
+                        None => bytes.push(byte(self.eval(value, 0)?)?),
+                    }
+                }
+
+                Ok(bytes)
+            },
+            Statement::Words(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    let (ll, hh) = word(self.eval(value, 0)?)?;
+                    bytes.push(ll);
+                    bytes.push(hh);
+                }
+
+                Ok(bytes)
+            },
+            Statement::Binary(data) => Ok(data.clone()),
+        }
+    }
+
+    fn operand(&self, text: &str) -> std::result::Result<Operand, String> {
+        let lower = text.to_ascii_lowercase();
+        if lower == "sp" {
+            return Ok(Operand::Sp);
+        }
+
+        if let Some(reg) = lower.strip_prefix('r').and_then(register) {
+            return Ok(Operand::Reg(reg));
+        }
+
+        self.eval(text, 0).map(Operand::Imm)
+    }
+
+    /// Evaluates a sum of numbers, labels and constants, e.g. `sprite+4`.
+    fn eval(&self, expr: &str, depth: usize) -> std::result::Result<i32, String> {
+        if depth > MAX_EVAL_DEPTH {
+            return Err(format!("Recursive constant: {}", expr));
+        }
+
+        let mut value: i32 = 0;
+        let mut negate = false;
+        let mut term = String::new();
+
+        for c in expr.chars().chain(std::iter::once('+')) {
+            if c != '+' && c != '-' {
+                term.push(c);
+                continue;
+            }
+
+            let name = term.trim();
+            if name.is_empty() {
+                if c == '-' {
+                    negate = !negate;
+                }
+                continue;
+            }
+
+            let term_value = match parse_number(name) {
+                Some(n) => n,
+                None => match self.symbols.get(name) {
+                    Some(Symbol::Label(addr)) => *addr as i32,
+                    Some(Symbol::Constant(expr)) => self.eval(expr, depth + 1)?,
+                    None => return Err(format!("Unknown symbol: {}", name)),
+                },
+            };
+
+            value = if negate { value.wrapping_sub(term_value) } else { value.wrapping_add(term_value) };
+            negate = c == '-';
+            term.clear();
+        }
+
+        if !term.trim().is_empty() || expr.trim().is_empty() {
+            return Err(format!("Invalid expression: {}", expr));
+        }
+
+        Ok(value)
+    }
+
+    fn instruction(&self, mnemonic: &str, operands: &[Operand]) -> Option<[u8; 4]> {
+        use self::Operand::*;
+
+        if let ([Reg(x), Imm(v)], Some(opcode)) = (operands, reg_imm(mnemonic)) {
+            return imm(opcode, *x, *v);
+        }
+
+        if let Some((xy, xyz)) = alu(mnemonic) {
+            match *operands {
+                [Reg(x), Reg(y)] => return Some(encode(xy, yx(x, y), 0, 0)),
+                [Reg(x), Reg(y), Reg(z)] => return Some(encode(xyz, yx(x, y), z, 0)),
+                _ => (),
+            }
+        }
+
+        let code = match (mnemonic, operands) {
+            ("nop", &[]) => encode(Opcode::NOP, 0, 0, 0),
+            ("cls", &[]) => encode(Opcode::CLS, 0, 0, 0),
+            ("vblnk", &[]) => encode(Opcode::VBLNK, 0, 0, 0),
+            ("bgc", &[Imm(n)]) => encode(Opcode::BGC, 0, nibble(n)?, 0),
+            ("spr", &[Imm(v)]) => imm(Opcode::SPR, 0, v)?,
+            ("drw", &[Reg(x), Reg(y), Imm(v)]) => imm(Opcode::DRW_XY_HHLL, yx(x, y), v)?,
+            ("drw", &[Reg(x), Reg(y), Reg(z)]) => encode(Opcode::DRW_XYZ, yx(x, y), z, 0),
+            ("flip", &[Imm(h), Imm(v)]) if (0..=1).contains(&h) && (0..=1).contains(&v) =>
+                encode(Opcode::FLIP, 0, 0, (h << 1 | v) as u8),
+            ("snd0", &[]) => encode(Opcode::SND0, 0, 0, 0),
+            ("snd1", &[Imm(v)]) => imm(Opcode::SND1, 0, v)?,
+            ("snd2", &[Imm(v)]) => imm(Opcode::SND2, 0, v)?,
+            ("snd3", &[Imm(v)]) => imm(Opcode::SND3, 0, v)?,
+            // SNG AD, VTSR stores VT in the third byte and SR in the fourth.
+            ("sng", &[Imm(ad), Imm(vtsr)]) => {
+                let (sr, vt) = word(vtsr).ok()?;
+                encode(Opcode::SNG, byte(ad).ok()?, vt, sr)
+            },
+            ("jmp", &[Imm(v)]) => imm(Opcode::JMP, 0, v)?,
+            ("jmp", &[Reg(x)]) => encode(Opcode::JMP_R, x, 0, 0),
+            ("jmc", &[Imm(v)]) => imm(Opcode::JMC, 0, v)?,
+            ("jme", &[Reg(x), Reg(y), Imm(v)]) => imm(Opcode::JME, yx(x, y), v)?,
+            ("call", &[Imm(v)]) => imm(Opcode::CALL_HHLL, 0, v)?,
+            ("call", &[Reg(x)]) => encode(Opcode::CALL, x, 0, 0),
+            ("ret", &[]) => encode(Opcode::RET, 0, 0, 0),
+            ("ldi", &[Sp, Imm(v)]) => imm(Opcode::LDI_SP, 0, v)?,
+            ("ldm", &[Reg(x), Reg(y)]) => encode(Opcode::LDM_R, yx(x, y), 0, 0),
+            ("mov", &[Reg(x), Reg(y)]) => encode(Opcode::MOV, yx(x, y), 0, 0),
+            ("stm", &[Reg(x), Reg(y)]) => encode(Opcode::STM_XY, yx(x, y), 0, 0),
+            ("cmp", &[Reg(x), Reg(y)]) => encode(Opcode::CMP, yx(x, y), 0, 0),
+            ("tst", &[Reg(x), Reg(y)]) => encode(Opcode::TST, yx(x, y), 0, 0),
+            ("shl", &[Reg(x), Imm(n)]) => encode(Opcode::SHL, x, nibble(n)?, 0),
+            ("shr", &[Reg(x), Imm(n)]) => encode(Opcode::SHR, x, nibble(n)?, 0),
+            ("sar", &[Reg(x), Imm(n)]) => encode(Opcode::SAR, x, nibble(n)?, 0),
+            ("shl", &[Reg(x), Reg(y)]) => encode(Opcode::SHL_XY, yx(x, y), 0, 0),
+            ("shr", &[Reg(x), Reg(y)]) => encode(Opcode::SHR_XY, yx(x, y), 0, 0),
+            ("sar", &[Reg(x), Reg(y)]) => encode(Opcode::SAR_XY, yx(x, y), 0, 0),
+            ("push", &[Reg(x)]) => encode(Opcode::PUSH, x, 0, 0),
+            ("pop", &[Reg(x)]) => encode(Opcode::POP, x, 0, 0),
+            ("pushall", &[]) => encode(Opcode::PUSHALL, 0, 0, 0),
+            ("popall", &[]) => encode(Opcode::POPALL, 0, 0, 0),
+            ("pushf", &[]) => encode(Opcode::PUSHF, 0, 0, 0),
+            ("popf", &[]) => encode(Opcode::POPF, 0, 0, 0),
+            ("pal", &[Imm(v)]) => imm(Opcode::PAL, 0, v)?,
+            ("pal", &[Reg(x)]) => encode(Opcode::PAL_R, x, 0, 0),
+            ("not", &[Reg(x)]) => encode(Opcode::NOT, x, 0, 0),
+            ("not", &[Reg(x), Reg(y)]) => encode(Opcode::NOT_XY, yx(x, y), 0, 0),
+            ("neg", &[Reg(x)]) => encode(Opcode::NEG, x, 0, 0),
+            ("neg", &[Reg(x), Reg(y)]) => encode(Opcode::NEG_XY, yx(x, y), 0, 0),
+            (_, &[Imm(v)]) => {
+                let (opcode, suffix) = if let Some(suffix) = mnemonic.strip_prefix('j') {
+                    (Opcode::JX, suffix)
+                } else if let Some(suffix) = mnemonic.strip_prefix('c') {
+                    (Opcode::CX, suffix)
+                } else {
+                    return None;
+                };
+
+                imm(opcode, condition(suffix)?, v)?
+            },
+            _ => return None,
+        };
+
+        Some(code)
+    }
+}
+
+/// Assembles a source file into a raw ROM image.
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let mut assembler = Assembler::new();
+    assembler.add_file(path)?;
+    assembler.assemble()
+}
+
+/// Assembles source text into a raw ROM image. Included files are looked up
+/// relative to the working directory.
+pub fn assemble_str(source: &str) -> Result<Vec<u8>> {
+    let mut assembler = Assembler::new();
+    assembler.add_source("<source>", source, Path::new("."))?;
+    assembler.assemble()
+}
+
+/// Mnemonics taking `RX, HHLL`.
+fn reg_imm(mnemonic: &str) -> Option<Opcode> {
+    Some(match mnemonic {
+        "rnd" => Opcode::RND,
+        "snp" => Opcode::SNP,
+        "ldi" => Opcode::LDI,
+        "ldm" => Opcode::LDM_HHLL,
+        "stm" => Opcode::STM,
+        "addi" => Opcode::ADDI,
+        "subi" => Opcode::SUBI,
+        "cmpi" => Opcode::CMPI,
+        "andi" => Opcode::ANDI,
+        "tsti" => Opcode::TSTI,
+        "ori" => Opcode::ORI,
+        "xori" => Opcode::XORI,
+        "muli" => Opcode::MULI,
+        "divi" => Opcode::DIVI,
+        "modi" => Opcode::MODI,
+        "remi" => Opcode::REMI,
+        "noti" => Opcode::NOTI,
+        "negi" => Opcode::NEGI,
+        _ => return None,
+    })
+}
+
+/// Mnemonics taking either `RX, RY` or `RX, RY, RZ`.
+fn alu(mnemonic: &str) -> Option<(Opcode, Opcode)> {
+    Some(match mnemonic {
+        "add" => (Opcode::ADD_XY, Opcode::ADD_XYZ),
+        "sub" => (Opcode::SUB_XY, Opcode::SUB_XYZ),
+        "and" => (Opcode::AND_XY, Opcode::AND_XYZ),
+        "or" => (Opcode::OR_XY, Opcode::OR_XYZ),
+        "xor" => (Opcode::XOR_XY, Opcode::XOR_XYZ),
+        "mul" => (Opcode::MUL_XY, Opcode::MUL_XYZ),
+        "div" => (Opcode::DIV_XY, Opcode::DIV_XYZ),
+        "mod" => (Opcode::MOD_XY, Opcode::MOD_XYZ),
+        "rem" => (Opcode::REM_XY, Opcode::REM_XYZ),
+        _ => return None,
+    })
+}
+
+/// Condition code of a Jx or Cx mnemonic suffix. `C` and `NC` are aliases of `B` and `AE`.
+fn condition(suffix: &str) -> Option<u8> {
+    match suffix {
+        "c" => Some(JMP_TYPE::B as u8),
+        "nc" => Some(JMP_TYPE::AE as u8),
+        _ => (0..16).filter_map(JMP_TYPE::from_u8)
+            .find(|condition| format!("{:?}", condition).eq_ignore_ascii_case(suffix))
+            .map(|condition| condition as u8),
+    }
+}
+
+#[inline(always)]
+fn encode(opcode: Opcode, yx: u8, ll: u8, hh: u8) -> [u8; 4] {
+    [opcode as u8, yx, ll, hh]
+}
+
+fn imm(opcode: Opcode, yx: u8, value: i32) -> Option<[u8; 4]> {
+    let (ll, hh) = word(value).ok()?;
+    Some(encode(opcode, yx, ll, hh))
+}
+
+#[inline(always)]
+fn yx(x: u8, y: u8) -> u8 {
+    y << 4 | x
+}
+
+fn nibble(value: i32) -> Option<u8> {
+    if (0..=0xf).contains(&value) { Some(value as u8) } else { None }
+}
+
+fn byte(value: i32) -> std::result::Result<u8, String> {
+    if (-0x80..=0xff).contains(&value) {
+        Ok(value as u8)
+    } else {
+        Err(format!("Byte out of range: {}", value))
+    }
+}
+
+/// Splits a 16-bit value into its low and high byte.
+fn word(value: i32) -> std::result::Result<(u8, u8), String> {
+    if (-0x8000..=0xffff).contains(&value) {
+        Ok((value as u8, (value >> 8) as u8))
+    } else {
+        Err(format!("Word out of range: {}", value))
+    }
+}
+
+/// Register number of `R0`-`RF`, also accepting decimal `R10`-`R15`.
+fn register(name: &str) -> Option<u8> {
+    match name.len() {
+        1 => u8::from_str_radix(name, 16).ok(),
+        2 => name.parse().ok().filter(|r| (10..16).contains(r)),
+        _ => None,
+    }
+}
+
+/// Parses decimal, `0x`/`#`/`$` hexadecimal and `0b` binary numbers.
+fn parse_number(text: &str) -> Option<i32> {
+    let lower = text.to_ascii_lowercase();
+
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')).or_else(|| lower.strip_prefix('$')) {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+
+    i32::from_str_radix(digits, radix).ok()
+}
+
+/// Contents of a double quoted string, supporting `\"`, `\\`, `\n` and `\0` escapes.
+fn parse_string(text: &str) -> Option<Vec<u8>> {
+    let inner = text.strip_prefix('"')?.strip_suffix('"')?;
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                '0' => '\0',
+                c => c,
+            },
+            c => c,
+        };
+
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    Some(bytes)
+}
+
+fn unquote(text: &str) -> &str {
+    let text = text.trim();
+    text.strip_prefix('"').and_then(|t| t.strip_suffix('"')).unwrap_or(text)
+}
+
+/// Removes a `;` comment, ignoring semicolons inside strings.
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => (),
+        }
+    }
+
+    text
+}
+
+/// Splits comma separated operands, ignoring commas inside strings.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut operand = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                operands.push(operand.trim().to_string());
+                operand.clear();
+                continue;
+            },
+            _ => (),
+        }
+
+        operand.push(c);
+    }
+
+    if !operand.trim().is_empty() || !operands.is_empty() {
+        operands.push(operand.trim().to_string());
+    }
+
+    operands
+}
+
+/// Returns the rest of `text` if it starts with the keyword `keyword`.
+fn strip_keyword<'a>(text: &'a str, keyword: &str) -> Option<&'a str> {
+    let (word, rest) = match text.find(char::is_whitespace) {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+
+    if word.eq_ignore_ascii_case(keyword) { Some(rest.trim()) } else { None }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => (),
+        _ => return false,
+    }
+
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::{assemble_str, assemble_file};
+    use crate::error::Error;
+
+    #[test]
+    fn test_assemble() {
+        let rom = assemble_str("
+            ; Draw a sprite and loop forever.
+            SPRITE_W equ 1
+            start:  ldi r0, 5
+                    LDI RA, -1
+                    spr #0100 + SPRITE_W
+                    drw r0, r15, sprite
+            loop:   vblnk
+                    jmp loop
+            sprite: db 0x12, \"a;b\", 0b101
+                    dw sprite, $BEEF
+        ").unwrap();
+
+        assert_eq!(rom, vec![
+            0x20, 0x00, 0x05, 0x00,
+            0x20, 0x0a, 0xff, 0xff,
+            0x04, 0x00, 0x01, 0x01,
+            0x05, 0xf0, 0x18, 0x00,
+            0x02, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x10, 0x00,
+            0x12, b'a', b';', b'b', 0x05,
+            0x18, 0x00, 0xef, 0xbe,
+        ]);
+    }
+
+    #[test]
+    fn test_conditions() {
+        let rom = assemble_str("jz 0x1234\njnz 0\njg 0\njl 0\njle 0\njc 0\njnc 0\ncge 0x1234\nco 0").unwrap();
+        let codes: Vec<(u8, u8)> = rom.chunks(4).map(|i| (i[0], i[1])).collect();
+
+        assert_eq!(&rom[..4], &[0x12, 0x00, 0x34, 0x12]);
+        assert_eq!(codes, vec![
+            (0x12, 0x0), (0x12, 0x1), (0x12, 0xb), (0x12, 0xd), (0x12, 0xe),
+            (0x12, 0x9), (0x12, 0x8), (0x17, 0xc), (0x17, 0x5),
+        ]);
+    }
+
+    #[test]
+    fn test_operand_forms() {
+        let rom = assemble_str("
+            jmp r3
+            ldi sp, 0xfdf0
+            ldm r1, r2
+            ldm r1, 0x2000
+            shl r1, 3
+            shl r1, r2
+            add r1, r2, r3
+            not r4
+            flip 1, 0
+            sng 0x42, 0xf381
+            bgc 0xf
+        ").unwrap();
+
+        assert_eq!(rom, vec![
+            0x16, 0x03, 0x00, 0x00,
+            0x21, 0x00, 0xf0, 0xfd,
+            0x23, 0x21, 0x00, 0x00,
+            0x22, 0x01, 0x00, 0x20,
+            0xb0, 0x01, 0x03, 0x00,
+            0xb3, 0x21, 0x00, 0x00,
+            0x42, 0x21, 0x03, 0x00,
+            0xe1, 0x04, 0x00, 0x00,
+            0x08, 0x00, 0x00, 0x02,
+            0x0e, 0x42, 0xf3, 0x81,
+            0x03, 0x00, 0x0f, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = vec![
+            ("nop\nfoo r1", 2, "Invalid instruction"),
+            ("ldi r0, missing", 1, "Unknown symbol"),
+            ("a:\na:", 2, "Duplicate symbol"),
+            ("x equ y\ny equ x\nldi r0, x", 3, "Recursive constant"),
+            ("db 256", 1, "Byte out of range"),
+            ("bgc 16", 1, "Invalid instruction"),
+            ("include \"/nonexistent/rusty16.s\"", 1, "Can't read"),
+        ];
+
+        for (source, line, message) in cases {
+            match assemble_str(source) {
+                Err(Error::Asm { line: l, message: m, .. }) if l == line && m.starts_with(message) => (),
+                res => panic!("{:?}: unexpected result: {:?}", source, res),
+            }
+        }
+    }
+
+    #[test]
+    fn test_include_importbin() {
+        let dir = std::env::temp_dir().join("rusty16_test_asm");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.s"), "include \"lib.s\"\nldi r0, data\nimportbin data.bin 1 2 data").unwrap();
+        std::fs::write(dir.join("lib.s"), "ret").unwrap();
+        std::fs::write(dir.join("data.bin"), [0xaa, 0xbb, 0xcc, 0xdd]).unwrap();
+
+        let rom = assemble_file(dir.join("main.s")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rom, vec![
+            0x15, 0x00, 0x00, 0x00,
+            0x20, 0x00, 0x08, 0x00,
+            0xbb, 0xcc,
+        ]);
+    }
+}
@@ -0,0 +1,161 @@
+#[cfg(feature = "sdl")]
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use byteorder::{LittleEndian, WriteBytesExt};
+use crate::sound::SAMPLE_RATE;
+#[cfg(feature = "sdl")]
+use crate::sound::SAMPLES_PER_FRAME;
+#[cfg(feature = "sdl")]
+use crate::surface::sdl_context;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use log::error;
+
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// Upper bound of samples waiting in the SDL queue. Anything above it
+/// is dropped to keep audio latency bounded when the host falls behind.
+#[cfg(feature = "sdl")]
+const MAX_QUEUED_SAMPLES: u32 = SAMPLES_PER_FRAME as u32 * 6;
+
+/// Audio backend consuming signed 16-bit mono samples at `SAMPLE_RATE`.
+pub trait AudioSink {
+    fn init(&mut self);
+    fn queue(&mut self, samples: &[i16]);
+}
+
+pub struct NullAudioSink;
+#[cfg(feature = "sdl")]
+pub struct SdlAudioSink {
+    queue: AudioQueue<i16>,
+}
+
+/// Renders the sample stream into a 16-bit mono PCM `.wav` file.
+/// The header is patched after every queued frame, so the file stays
+/// valid even if the emulator is killed.
+pub struct WavAudioSink {
+    writer: BufWriter<File>,
+    data_size: u32,
+}
+
+impl AudioSink for NullAudioSink {
+    fn init(&mut self) {}
+    fn queue(&mut self, _samples: &[i16]) {}
+}
+
+#[cfg(feature = "sdl")]
+impl SdlAudioSink {
+    pub fn open() -> Self {
+        let audio_subsys = sdl_context().audio().unwrap();
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(1),
+            samples: Some(1024),
+        };
+
+        let queue = audio_subsys
+            .open_queue::<i16, _>(None, &spec)
+            .unwrap();
+
+        SdlAudioSink {
+            queue,
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
+impl AudioSink for SdlAudioSink {
+    fn init(&mut self) {
+        self.queue.clear();
+        self.queue.resume();
+    }
+
+    fn queue(&mut self, samples: &[i16]) {
+        let queued = self.queue.size() / std::mem::size_of::<i16>() as u32;
+        if queued < MAX_QUEUED_SAMPLES {
+            self.queue.queue(samples);
+        }
+    }
+}
+
+impl WavAudioSink {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        let mut sink = WavAudioSink {
+            writer: BufWriter::new(File::create(path)?),
+            data_size: 0,
+        };
+
+        sink.write_header()?;
+        Ok(sink)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        let block_align = std::mem::size_of::<i16>() as u16;
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_u32::<LittleEndian>(WAV_HEADER_SIZE - 8 + self.data_size)?;
+        self.writer.write_all(b"WAVE")?;
+
+        self.writer.write_all(b"fmt ")?;
+        self.writer.write_u32::<LittleEndian>(16)?;
+        self.writer.write_u16::<LittleEndian>(1)?;
+        self.writer.write_u16::<LittleEndian>(1)?;
+        self.writer.write_u32::<LittleEndian>(SAMPLE_RATE)?;
+        self.writer.write_u32::<LittleEndian>(SAMPLE_RATE * block_align as u32)?;
+        self.writer.write_u16::<LittleEndian>(block_align)?;
+        self.writer.write_u16::<LittleEndian>(16)?;
+
+        self.writer.write_all(b"data")?;
+        self.writer.write_u32::<LittleEndian>(self.data_size)?;
+        Ok(())
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> std::io::Result<()> {
+        self.writer.seek(SeekFrom::Start((WAV_HEADER_SIZE + self.data_size) as u64))?;
+        for sample in samples {
+            self.writer.write_i16::<LittleEndian>(*sample)?;
+        }
+        self.data_size += std::mem::size_of_val(samples) as u32;
+
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+impl AudioSink for WavAudioSink {
+    fn init(&mut self) {}
+
+    fn queue(&mut self, samples: &[i16]) {
+        if let Err(err) = self.write_samples(samples) {
+            error!("Failed to write WAV samples: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audio::{AudioSink, WavAudioSink};
+    use byteorder::{ByteOrder, LittleEndian};
+
+    #[test]
+    fn test_wav_sink() {
+        let path = std::env::temp_dir().join("rusty16_test_wav_sink.wav");
+        let path = path.to_str().unwrap();
+
+        let mut sink = WavAudioSink::create(path).unwrap();
+        sink.queue(&[0, 1, -1]);
+        sink.queue(&[0x1234]);
+
+        let wav = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(LittleEndian::read_u32(&wav[4..8]), 36 + 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(LittleEndian::read_u32(&wav[24..28]), 44100);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(LittleEndian::read_u32(&wav[40..44]), 8);
+        assert_eq!(&wav[44..], &[0x00, 0x00, 0x01, 0x00, 0xff, 0xff, 0x34, 0x12]);
+    }
+}
@@ -1,8 +1,244 @@
 extern crate log;
 extern crate rusty16;
 
-use std::env;
+use std::{env, io, process};
+use std::convert::TryFrom;
 use env_logger::Env;
+use rusty16::audio::{AudioSink, NullAudioSink, WavAudioSink};
+use rusty16::debugger::Debugger;
+use rusty16::gdb::GdbStub;
+use rusty16::input::KeyMap;
+use rusty16::movie::Movie;
+#[cfg(feature = "sdl")]
+use rusty16::surface::SdlSurface;
+use rusty16::surface::{Surface, TestSurface};
+use rusty16::trace::{OpcodeClass, TraceFormat, Tracer};
+
+const USAGE: &str = "Usage: rusty16 [OPTIONS] [ROM]
+
+Options:
+    --wav FILE      Write audio to a WAV file instead of the sound card
+    --keys1 KEYS    Key bindings of controller 1, e.g. up=W,down=S,a=J,start=Return
+    --keys2 KEYS    Key bindings of controller 2
+    --debug         Start in the interactive debugger, type 'help' at its prompt
+    --gdb ADDR      Wait for a GDB remote protocol client on ADDR, e.g. localhost:1234
+    --state FILE    Quick-save file, defaults to the ROM path with .state appended
+    --rewind SECS   Seconds of rewind history, defaults to 10, 0 disables rewinding
+    --seed N        Seed of the RND instruction, random by default, reported in traces
+    --strict        Refuse ROMs whose checksum doesn't match their header
+
+Screenshots:
+    --screenshots PREFIX    Path prefix of F12 screenshots, defaults to the ROM path with - appended
+    --scale N               Size of screenshots and --dump images in multiples of 320x240, defaults to 1
+
+Movies:
+    --record FILE   Record the controller input of every frame and the seed to FILE
+    --play FILE     Play the input recorded in FILE, the keyboard takes over once it ends
+
+Headless:
+    --headless      Run without a window or sound card as fast as possible, until the movie
+                    given with --play ends or for --frames, then print the framebuffer hash
+    --frames N      Frames to run headless
+    --expect HASH   Exit with 3 if the framebuffer hash differs from the hexadecimal HASH
+    --dump FILE     Write the last frame to FILE, a PNG or PPM image by its extension
+
+Tracing:
+    --trace FILE                Write every executed instruction to FILE
+    --trace-format FORMAT       text (default) or binary
+    --trace-addr FIRST-LAST     Only trace instructions at these addresses, e.g. 0x100-0x1ff
+    --trace-class CLASSES       Only trace these opcode classes, e.g. jump,load,store
+    --trace-frames FIRST-LAST   Only trace these frames
+
+Opcode classes: misc, jump, load, store, add, sub, and, or, xor, mul, div, shift, stack, palette, not.
+
+Buttons: up, down, left, right, select, start, a, b. Keys use SDL key names.
+Hotkeys: F5 quick-saves the machine to the state file, F9 quick-loads it,
+holding Backspace rewinds and F12 saves a PNG screenshot.
+
+The ROM path may also be given in the RUSTY16_ROM environment variable.";
+
+struct Options {
+    rom: String,
+    wav: Option<String>,
+    key_maps: [KeyMap; 2],
+    debug: bool,
+    gdb: Option<String>,
+    state: String,
+    rewind: u64,
+    seed: Option<u64>,
+    strict: bool,
+    screenshots: String,
+    scale: usize,
+    record: Option<String>,
+    play: Option<String>,
+    headless: bool,
+    frames: Option<u64>,
+    expect: Option<u32>,
+    dump: Option<String>,
+    trace: Option<String>,
+    trace_format: TraceFormat,
+    trace_addr: Option<(u16, u16)>,
+    trace_classes: Vec<OpcodeClass>,
+    trace_frames: Option<(u64, u64)>,
+}
+
+impl Options {
+    fn parse() -> Options {
+        let mut rom = env::var("RUSTY16_ROM").ok();
+        let mut wav = None;
+        let mut key_maps = [KeyMap::player1(), KeyMap::player2()];
+        let mut debug = false;
+        let mut gdb = None;
+        let mut state = None;
+        let mut rewind = 10;
+        let mut seed = None;
+        let mut strict = false;
+        let mut screenshots = None;
+        let mut scale = 1;
+        let mut record = None;
+        let mut play = None;
+        let mut headless = false;
+        let mut frames = None;
+        let mut expect = None;
+        let mut dump = None;
+        let mut trace = None;
+        let mut trace_format = TraceFormat::Text;
+        let mut trace_addr = None;
+        let mut trace_classes = Vec::new();
+        let mut trace_frames = None;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--wav" => wav = Some(args.next().unwrap_or_else(|| usage("--wav requires a file"))),
+                "--keys1" | "--keys2" => {
+                    let player = if arg == "--keys1" { 0 } else { 1 };
+                    let spec = args.next().unwrap_or_else(|| usage(&format!("{} requires key bindings", arg)));
+                    key_maps[player] = key_maps[player].clone().parse(&spec).unwrap_or_else(|err| usage(&err));
+                },
+                "--debug" => debug = true,
+                "--gdb" => gdb = Some(args.next().unwrap_or_else(|| usage("--gdb requires an address"))),
+                "--state" => state = Some(args.next().unwrap_or_else(|| usage("--state requires a file"))),
+                "--rewind" => {
+                    let seconds = args.next().unwrap_or_else(|| usage("--rewind requires a number of seconds"));
+                    rewind = seconds.parse().unwrap_or_else(|_| usage(&format!("Invalid number: {}", seconds)));
+                },
+                "--seed" => {
+                    let number = args.next().unwrap_or_else(|| usage("--seed requires a number"));
+                    seed = Some(parse_number(&number).unwrap_or_else(|| usage(&format!("Invalid number: {}", number))));
+                },
+                "--strict" => strict = true,
+                "--screenshots" => screenshots = Some(args.next().unwrap_or_else(|| usage("--screenshots requires a path prefix"))),
+                "--scale" => {
+                    let number = args.next().unwrap_or_else(|| usage("--scale requires a number"));
+                    scale = match number.parse() {
+                        Ok(scale) if scale > 0 => scale,
+                        _ => usage(&format!("Invalid scale: {}", number)),
+                    };
+                },
+                "--record" => record = Some(args.next().unwrap_or_else(|| usage("--record requires a file"))),
+                "--play" => play = Some(args.next().unwrap_or_else(|| usage("--play requires a file"))),
+                "--headless" => headless = true,
+                "--frames" => {
+                    let number = args.next().unwrap_or_else(|| usage("--frames requires a number"));
+                    frames = Some(parse_number(&number).unwrap_or_else(|| usage(&format!("Invalid number: {}", number))));
+                },
+                "--expect" => {
+                    let hash = args.next().unwrap_or_else(|| usage("--expect requires a hash"));
+                    let hex = hash.strip_prefix("0x").unwrap_or(&hash);
+                    expect = Some(u32::from_str_radix(hex, 16).unwrap_or_else(|_| usage(&format!("Invalid hash: {}", hash))));
+                },
+                "--dump" => dump = Some(args.next().unwrap_or_else(|| usage("--dump requires a file"))),
+                "--trace" => trace = Some(args.next().unwrap_or_else(|| usage("--trace requires a file"))),
+                "--trace-format" => {
+                    let format = args.next().unwrap_or_else(|| usage("--trace-format requires a format"));
+                    trace_format = format.parse().unwrap_or_else(|err: String| usage(&err));
+                },
+                "--trace-addr" => {
+                    let range = args.next().unwrap_or_else(|| usage("--trace-addr requires an address range"));
+                    trace_addr = Some(parse_range(&range).unwrap_or_else(|err| usage(&err)));
+                },
+                "--trace-class" => {
+                    let classes = args.next().unwrap_or_else(|| usage("--trace-class requires opcode classes"));
+                    for class in classes.split(',') {
+                        trace_classes.push(class.parse().unwrap_or_else(|err: String| usage(&err)));
+                    }
+                },
+                "--trace-frames" => {
+                    let range = args.next().unwrap_or_else(|| usage("--trace-frames requires a frame range"));
+                    trace_frames = Some(parse_range(&range).unwrap_or_else(|err| usage(&err)));
+                },
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                _ if arg.starts_with('-') => usage(&format!("Unknown option: {}", arg)),
+                _ => rom = Some(arg),
+            }
+        }
+
+        let rom = rom.unwrap_or_else(|| usage("No ROM given"));
+        let state = state.unwrap_or_else(|| format!("{}.state", rom));
+        let screenshots = screenshots.unwrap_or_else(|| format!("{}-", rom));
+
+        if headless && play.is_none() && frames.is_none() {
+            usage("--headless requires --play or --frames");
+        }
+        if !headless && (frames.is_some() || expect.is_some() || dump.is_some()) {
+            usage("--frames, --expect and --dump require --headless");
+        }
+
+        Options {
+            rom,
+            wav,
+            key_maps,
+            debug,
+            gdb,
+            state,
+            rewind,
+            seed,
+            strict,
+            screenshots,
+            scale,
+            record,
+            play,
+            headless,
+            frames,
+            expect,
+            dump,
+            trace,
+            trace_format,
+            trace_addr,
+            trace_classes,
+            trace_frames,
+        }
+    }
+}
+
+/// Parses a decimal or 0x prefixed hexadecimal number.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Parses `FIRST-LAST` of decimal or 0x prefixed hexadecimal numbers.
+fn parse_range<T: TryFrom<u64>>(range: &str) -> Result<(T, T), String> {
+    let parse = |text: &str| {
+        parse_number(text).and_then(|number| T::try_from(number).ok()).ok_or_else(|| format!("Invalid range: {}", range))
+    };
+
+    match range.split_once('-') {
+        Some((first, last)) => Ok((parse(first)?, parse(last)?)),
+        None => Err(format!("Invalid range: {}, expected FIRST-LAST", range)),
+    }
+}
+
+fn usage(err: &str) -> ! {
+    eprintln!("{}\n\n{}", err, USAGE);
+    process::exit(2);
+}
 
 fn main() {
     let log_env = Env::default()
@@ -11,13 +247,145 @@ fn main() {
 
     env_logger::init_from_env(log_env);
 
-    // TODO(alexyer): Implement proper cli.
-    let filename = match env::var("RUSTY16_ROM") {
-        Ok(filename) => filename,
-        Err(err) => panic!("{:?}", err),
+    let options = Options::parse();
+
+    if !options.headless {
+        run_sdl(&options);
+        return;
+    }
+
+    match open_wav(&options) {
+        Some(sink) => run(rusty16::Rusty16::<TestSurface, _>::with_audio_sink(sink), &options),
+        None => run(rusty16::Rusty16::<TestSurface, _>::with_audio_sink(NullAudioSink), &options),
+    }
+}
+
+/// Runs in an SDL window, with the sound card unless `--wav` is given.
+#[cfg(feature = "sdl")]
+fn run_sdl(options: &Options) {
+    match open_wav(options) {
+        Some(sink) => run(rusty16::Rusty16::<SdlSurface, _>::with_audio_sink(sink), options),
+        None => run(rusty16::Rusty16::new(), options),
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_sdl(_options: &Options) {
+    usage("Built without the sdl feature, only --headless is available");
+}
+
+fn open_wav(options: &Options) -> Option<WavAudioSink> {
+    options.wav.as_ref().map(|wav| WavAudioSink::create(wav).unwrap_or_else(|err| {
+        eprintln!("{}: {}", wav, err);
+        process::exit(1);
+    }))
+}
+
+fn run<'a, S: Surface, A: AudioSink>(mut emulator: rusty16::Rusty16<'a, S, A>, options: &'a Options) {
+    emulator
+        .rom_path(&options.rom)
+        .state_path(&options.state)
+        .rewind(options.rewind)
+        .strict_checksum(options.strict)
+        .screenshot_path(&options.screenshots, options.scale)
+        .key_map(0, options.key_maps[0].clone())
+        .key_map(1, options.key_maps[1].clone());
+
+    if let Some(seed) = options.seed {
+        emulator.seed(seed);
+    }
+
+    if options.record.is_some() {
+        emulator.record_movie();
+    }
+    if let Some(ref path) = options.play {
+        let movie = Movie::open(path).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        });
+
+        emulator.play_movie(movie);
+    }
+
+    if let Some(ref path) = options.trace {
+        let mut tracer = Tracer::create(path, options.trace_format).unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        });
+
+        if let Some((first, last)) = options.trace_addr {
+            tracer.address_range(first, last);
+        }
+        for class in options.trace_classes.iter() {
+            tracer.opcode_class(*class);
+        }
+        if let Some((first, last)) = options.trace_frames {
+            tracer.frames(first, last);
+        }
+
+        emulator.tracer(tracer);
+    }
+
+    // Only false for a headless run with an unexpected framebuffer hash.
+    let result = if let Some(ref addr) = options.gdb {
+        GdbStub::new(&mut emulator).listen(addr.as_str()).map(|()| true)
+    } else if options.debug {
+        Debugger::new(&mut emulator).run(io::stdin().lock(), io::stdout()).map(|()| true)
+    } else if options.headless {
+        run_headless(&mut emulator, options)
+    } else {
+        emulator.run().map(|()| true)
     };
 
-    rusty16::Rusty16::new()
-        .rom_path(&filename)
-        .run();
+    // Also saved after errors, a movie of the session is the bug report.
+    if let (Some(path), Some(movie)) = (&options.record, emulator.movie()) {
+        if let Err(err) = movie.save(path) {
+            eprintln!("{}: {}", path, err);
+        }
+    }
+
+    // Flushes the trace, process::exit doesn't run destructors.
+    drop(emulator);
+
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(3),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        },
+    }
+}
+
+/// Runs as fast as possible for `--frames` or until the movie ends, then prints the
+/// framebuffer hash and dumps the frame. Returns `false` if the hash isn't the expected one.
+fn run_headless<S: Surface, A: AudioSink>(emulator: &mut rusty16::Rusty16<S, A>, options: &Options) -> rusty16::Result<bool> {
+    emulator.init()?;
+
+    loop {
+        let done = match options.frames {
+            Some(frames) => emulator.frame() >= frames,
+            None => emulator.movie_finished(),
+        };
+        if done || emulator.quit_requested() {
+            break;
+        }
+
+        emulator.run_frame()?;
+    }
+
+    let hash = emulator.screen().checksum();
+    println!("Frame {}: {:08x}", emulator.frame(), hash);
+
+    if let Some(ref path) = options.dump {
+        emulator.save_screenshot(path, options.scale)?;
+    }
+
+    match options.expect {
+        Some(expected) if expected != hash => {
+            eprintln!("Framebuffer hash mismatch: expected {:08x}, got {:08x}", expected, hash);
+            Ok(false)
+        },
+        _ => Ok(true),
+    }
 }
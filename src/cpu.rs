@@ -1,23 +1,39 @@
-use log::{debug, info};
+use log::info;
 use crate::memory::Memory;
 use crate::instruction::Instruction;
-use std::convert::TryInto;
 use crate::opcode::{Opcode, JMP_TYPE};
 use crate::flags::CpuFlags;
 use enum_primitive::FromPrimitive;
 use crate::screen::Screen;
-use crate::surface::SdlSurface;
-use rand::Rng;
+use crate::surface::Surface;
+use crate::sound::Sound;
+use crate::audio::AudioSink;
+use crate::error::{Error, Result};
+use crate::rng::Rng;
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 pub const INSTRUCTION_SIZE: usize = 4;
 const STACK_ENTRY_SIZE: usize = 2;
 
+/// The stack starts at 0xFDF0 unless LDI SP moves it, and grows up to the
+/// controller ports at 0xFFF0.
+const STACK_START: u16 = 0xfdf0;
+const STACK_END: u16 = 0xfff0;
+
+#[derive(Clone)]
 pub struct Cpu {
     pc: u16,
     sp: u16,
+    /// Bottom of the stack as set by reset or LDI SP, popping below it underflows.
+    stack_base: u16,
     r: [i16; 16],
 
     flags: CpuFlags,
+
+    /// Seed of `rng`, kept to report it.
+    seed: u64,
+    rng: Rng,
 }
 
 impl Cpu {
@@ -26,15 +42,77 @@ impl Cpu {
         info!("Initial program counter address set to: {:#X}", self.pc);
     }
 
-    pub fn exec_instruction(&mut self, mem: &mut Memory, screen: &mut Screen<SdlSurface>) {
-        let instruction = self.read_instruction(mem);
-        let opcode = instruction.opcode().unwrap_or_else(|| {
-           panic!("Unrecognized opcode: {:#04x}. Instruction: {:X?}", instruction.0[0], instruction.0)
-        });
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn r(&self) -> &[i16; 16] {
+        &self.r
+    }
+
+    pub fn flags(&self) -> CpuFlags {
+        self.flags
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    pub fn set_r(&mut self, r: usize, val: i16) {
+        self.r[r] = val;
+    }
+
+    pub fn set_flags(&mut self, flags: CpuFlags) {
+        self.flags = flags;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Restarts the random numbers drawn by RND from `seed`.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Rng(seed);
+        info!("Random number generator seeded with: {:#X}", seed);
+    }
+
+    /// Writes PC, SP, the stack base, R0-RF, flags, the seed and the RNG state for a save state.
+    pub(crate) fn save_state<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u16::<LittleEndian>(self.pc)?;
+        writer.write_u16::<LittleEndian>(self.sp)?;
+        writer.write_u16::<LittleEndian>(self.stack_base)?;
+        for r in self.r.iter() {
+            writer.write_i16::<LittleEndian>(*r)?;
+        }
+        writer.write_u8(self.flags.0)?;
+        writer.write_u64::<LittleEndian>(self.seed)?;
+        writer.write_u64::<LittleEndian>(self.rng.0)
+    }
+
+    pub(crate) fn load_state<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        self.pc = reader.read_u16::<LittleEndian>()?;
+        self.sp = reader.read_u16::<LittleEndian>()?;
+        self.stack_base = reader.read_u16::<LittleEndian>()?;
+        reader.read_i16_into::<LittleEndian>(&mut self.r)?;
+        self.flags = CpuFlags(reader.read_u8()?);
+        self.seed = reader.read_u64::<LittleEndian>()?;
+        self.rng = Rng(reader.read_u64::<LittleEndian>()?);
+
+        Ok(())
+    }
 
-        // if opcode != Opcode::VBLNK {
-        //     println!("OP: {:<10} I: {:<15}, PC: {:#04X?} SP: {:#X?} R: {:X?} F: {}", opcode, instruction.to_asm_str(), self.pc, self.sp, self.r, self.flags);
-        // }
+    pub fn exec_instruction<S: Surface, A: AudioSink>(&mut self, mem: &mut Memory, screen: &mut Screen<S>, sound: &mut Sound<A>) -> Result<()> {
+        let bytes = mem.instruction_bytes(self.pc);
+        let instruction = Instruction(&bytes);
+        let opcode = instruction.opcode().ok_or(Error::UnknownOpcode {
+            opcode: instruction.0[0],
+            pc: self.pc,
+        })?;
 
         match opcode {
             Opcode::NOP => { self.inc_pc() },
@@ -44,20 +122,27 @@ impl Cpu {
             Opcode::SPR => { screen.spr(instruction.ll() as u8, instruction.hh() as u8); self.inc_pc() },
             Opcode::DRW_XY_HHLL => { self.drw(instruction.x(), instruction.y(), instruction.ll(), instruction.hh(), &mem, screen) },
             Opcode::DRW_XYZ => { self.drw_xyz(instruction.x(), instruction.y(), instruction.z(), &mem, screen) },
-            Opcode::SND2 => { debug!("Unimplemented instruction SND2"); self.inc_pc() },
-            Opcode::SND3 => { debug!("Unimplemented instruction SND3"); self.inc_pc() },
-            Opcode::SNG => { debug!("Unimplemented instruction SNG"); self.inc_pc() },
-            Opcode::SNP => { debug!("Unimplemented instruction SNP"); self.inc_pc() },
+            Opcode::FLIP => { screen.flip(instruction.hh() & 0x2 > 0, instruction.hh() & 0x1 > 0); self.inc_pc() },
+            Opcode::SND0 => { sound.stop(); self.inc_pc() },
+            Opcode::SND1 => { sound.tone(500, little_endian!(instruction.ll(), instruction.hh())); self.inc_pc() },
+            Opcode::SND2 => { sound.tone(1000, little_endian!(instruction.ll(), instruction.hh())); self.inc_pc() },
+            Opcode::SND3 => { sound.tone(1500, little_endian!(instruction.ll(), instruction.hh())); self.inc_pc() },
+            Opcode::SNG => { sound.sng(instruction.0[1], instruction.ll(), instruction.hh()); self.inc_pc() },
+            Opcode::SNP => self.snp(instruction.x(), instruction.ll(), instruction.hh(), mem, sound),
             Opcode::LDI => self.ldi(instruction.x() as usize, instruction.ll(), instruction.hh()),
-            Opcode::CALL_HHLL => self.call_hhll(instruction.ll(), instruction.hh(), mem),
-            Opcode::CALL => self.call(instruction.x(), mem),
+            Opcode::CALL_HHLL => self.call_hhll(instruction.ll(), instruction.hh(), mem)?,
+            Opcode::CALL => self.call(instruction.x(), mem)?,
             Opcode::LDM_R => self.ldm_r(instruction.x(), instruction.y(), mem),
             Opcode::LDM_HHLL => self.ldm_hhll(instruction.x(), instruction.ll(), instruction.hh(), mem),
             Opcode::ANDI => self.andi(instruction.x(), instruction.ll(), instruction.hh()),
             Opcode::JMP => self.jmp(little_endian!(instruction.ll(), instruction.hh())),
-            Opcode::JX => self.jx(instruction.x(), instruction.ll(), instruction.hh()),
+            Opcode::JMC => self.jmc(instruction.ll(), instruction.hh()),
+            Opcode::JX => self.jx(instruction.x(), instruction.ll(), instruction.hh())?,
+            Opcode::JMP_R => self.jmp(self.r[instruction.x() as usize] as u16),
+            Opcode::CX => self.cx(instruction.x(), instruction.ll(), instruction.hh(), mem)?,
+            Opcode::LDI_SP => self.ldi_sp(instruction.ll(), instruction.hh()),
             Opcode::JME => self.jme(instruction.x(), instruction.y(), instruction.ll(), instruction.hh()),
-            Opcode::RET => self.ret(mem),
+            Opcode::RET => self.ret(mem)?,
             Opcode::SUBI => self.subi(instruction.x(), instruction.ll(), instruction.hh()),
             Opcode::MULI => self.muli(instruction.x(), instruction.ll(), instruction.hh()),
             Opcode::ADDI => self.addi(instruction.x(), instruction.ll(), instruction.hh()),
@@ -72,58 +157,95 @@ impl Cpu {
             Opcode::TST => self.tst(instruction.x(), instruction.y()),
             Opcode::DIV_XY => self.div_xy(instruction.x(), instruction.y()),
             Opcode::DIVI => self.divi(instruction.x(), instruction.ll(), instruction.hh()),
+            Opcode::DIV_XYZ => self.div_xyz(instruction.x(), instruction.y(), instruction.z()),
+            Opcode::MODI => self.modi(instruction.x(), instruction.ll(), instruction.hh()),
+            Opcode::MOD_XY => self.mod_xy(instruction.x(), instruction.y()),
+            Opcode::MOD_XYZ => self.mod_xyz(instruction.x(), instruction.y(), instruction.z()),
+            Opcode::REMI => self.remi(instruction.x(), instruction.ll(), instruction.hh()),
+            Opcode::REM_XY => self.rem_xy(instruction.x(), instruction.y()),
+            Opcode::REM_XYZ => self.rem_xyz(instruction.x(), instruction.y(), instruction.z()),
             Opcode::MUL_XY => self.mul_xy(instruction.x(), instruction.y()),
             Opcode::MUL_XYZ => self.mul_xyz(instruction.x(), instruction.y(), instruction.z()),
+            Opcode::XORI => self.xori(instruction.x(), instruction.ll(), instruction.hh()),
             Opcode::XOR_XY => self.xor_xy(instruction.x(), instruction.y()),
             Opcode::XOR_XYZ => self.xor_xyz(instruction.x(), instruction.y(), instruction.z()),
+            Opcode::ORI => self.ori(instruction.x(), instruction.ll(), instruction.hh()),
             Opcode::OR_XY => self.or_xy(instruction.x(), instruction.y()),
             Opcode::OR_XYZ => self.or_xyz(instruction.x(), instruction.y(), instruction.z()),
             Opcode::SUB_XY => self.sub_xy(instruction.x(), instruction.y()),
             Opcode::SUB_XYZ => self.sub_xyz(instruction.x(), instruction.y(), instruction.z()),
             Opcode::CMPI => self.cmpi(instruction.x(), instruction.ll(), instruction.hh()),
             Opcode::CMP => self.cmp(instruction.x(), instruction.y()),
-            Opcode::PUSHF => self.pushf(mem),
-            Opcode::PUSH => self.push(instruction.x(), mem),
-            Opcode::POP => self.pop(instruction.x(), mem),
+            Opcode::PUSHF => self.pushf(mem)?,
+            Opcode::POPF => self.popf(mem)?,
+            Opcode::PUSHALL => self.pushall(mem)?,
+            Opcode::POPALL => self.popall(mem)?,
+            Opcode::PUSH => self.push(instruction.x(), mem)?,
+            Opcode::POP => self.pop(instruction.x(), mem)?,
             Opcode::SHR => self.shr(instruction.x(), instruction.z()),
             Opcode::SHL => self.shl(instruction.x(), instruction.z()),
             Opcode::SHL_XY => self.shl_xy(instruction.x(), instruction.y()),
+            Opcode::SHR_XY => self.shr_xy(instruction.x(), instruction.y()),
+            Opcode::SAR_XY => self.sar_xy(instruction.x(), instruction.y()),
             Opcode::RND => self.rnd(instruction.x(), instruction.ll(), instruction.hh()),
             Opcode::SAR => self.sar(instruction.x(), instruction.z()),
-            Opcode::PAL => { debug!("Unimplemented instruction PAL"); self.inc_pc() },
+            Opcode::PAL => { screen.pal(little_endian!(instruction.ll(), instruction.hh()), mem); self.inc_pc() },
+            Opcode::PAL_R => { screen.pal(self.r[instruction.x() as usize] as u16, mem); self.inc_pc() },
+            Opcode::NOTI => self.noti(instruction.x(), instruction.ll(), instruction.hh()),
+            Opcode::NOT => self.not_xy(instruction.x(), instruction.x()),
+            Opcode::NOT_XY => self.not_xy(instruction.x(), instruction.y()),
+            Opcode::NEGI => self.negi(instruction.x(), instruction.ll(), instruction.hh()),
+            Opcode::NEG => self.neg_xy(instruction.x(), instruction.x()),
+            Opcode::NEG_XY => self.neg_xy(instruction.x(), instruction.y()),
         };
-    }
 
-    fn read_instruction<'a>(&self, mem: &'a mut Memory) -> Instruction<'a> {
-        Instruction(mem[self.pc as usize..self.pc as usize + INSTRUCTION_SIZE ].try_into().expect(""))
+        Ok(())
     }
 
     fn rnd(&mut self, x: u8, ll: u8, hh: u8) {
-        let mut rng = rand::thread_rng();
-        self.r[x as usize] = rng.gen_range(0..=little_endian!(ll, hh)) as i16;
+        self.r[x as usize] = self.rng.gen_range(little_endian!(ll, hh)) as i16;
         self.inc_pc();
     }
 
-    fn vblnk(&mut self, screen: &mut Screen<SdlSurface>) {
+    fn vblnk<S: Surface>(&mut self, screen: &mut Screen<S>) {
         if screen.vblank() {
             screen.clear_vblank();
             self.inc_pc();
         }
     }
 
-    fn drw(&mut self, x: u8, y: u8, ll: u8, hh: u8, mem: &Memory, screen: &mut Screen<SdlSurface>) {
-        screen.drw(self.r[x as usize], self.r[y as usize], little_endian!(ll, hh), mem);
+    fn drw<S: Surface>(&mut self, x: u8, y: u8, ll: u8, hh: u8, mem: &Memory, screen: &mut Screen<S>) {
+        let collision = screen.drw(self.r[x as usize], self.r[y as usize], little_endian!(ll, hh), mem);
+        self.set_collision(collision);
         self.inc_pc();
     }
 
-    fn drw_xyz(&mut self, x: u8, y: u8, z: u8, mem: &Memory, screen: &mut Screen<SdlSurface>) {
-        screen.drw(self.r[x as usize], self.r[y as usize],self.r[z as usize] as u16, mem);
+    fn drw_xyz<S: Surface>(&mut self, x: u8, y: u8, z: u8, mem: &Memory, screen: &mut Screen<S>) {
+        let collision = screen.drw(self.r[x as usize], self.r[y as usize],self.r[z as usize] as u16, mem);
+        self.set_collision(collision);
+        self.inc_pc();
+    }
+
+    #[inline(always)]
+    fn set_collision(&mut self, collision: bool) {
+        if collision {
+            self.flags.set_c();
+        } else {
+            self.flags.clear_c();
+        }
+    }
+
+    fn snp<A: AudioSink>(&mut self, x: u8, ll: u8, hh: u8, mem: &Memory, sound: &mut Sound<A>) {
+        let src = self.r[x as usize] as u16 as usize;
+        let freq = little_endian!(mem[src], mem[(src + 1) & 0xffff]);
+
+        sound.snp(freq, little_endian!(ll, hh));
         self.inc_pc();
     }
 
     #[inline(always)]
     fn inc_pc(&mut self) {
-        self.pc += INSTRUCTION_SIZE as u16;
+        self.pc = self.pc.wrapping_add(INSTRUCTION_SIZE as u16);
     }
 
     #[inline(always)]
@@ -142,29 +264,56 @@ impl Cpu {
         self.inc_pc();
     }
 
-    fn call_hhll(&mut self, ll: u8, hh: u8, mem: &mut Memory) {
-        mem[self.sp as usize] = (self.pc & 0x00ff) as u8;
-        mem[self.sp as usize + 1] = (self.pc >> 8) as u8;
-        self.inc_sp();
-        self.jmp(little_endian!(ll, hh));
+    /// LDI SP: moves the stack, the new SP becomes its bottom.
+    fn ldi_sp(&mut self, ll: u8, hh: u8) {
+        self.sp = little_endian!(ll, hh);
+        self.stack_base = self.sp;
+        self.inc_pc();
     }
 
-    fn call(&mut self, x: u8, mem: &mut Memory) {
-        mem[self.sp as usize] = (self.pc & 0x00ff) as u8;
-        mem[self.sp as usize + 1] = (self.pc >> 8) as u8;
+    fn push_op(&mut self, val: u16, mem: &mut Memory) -> Result<()> {
+        let top = self.sp.checked_add(STACK_ENTRY_SIZE as u16);
+        if top.is_none_or(|top| top > STACK_END) {
+            return Err(Error::StackOverflow { sp: self.sp, pc: self.pc });
+        }
+
+        mem[self.sp as usize] = (val & 0x00ff) as u8;
+        mem[self.sp as usize + 1] = (val >> 8) as u8;
         self.inc_sp();
-        self.jmp(self.r[x as usize] as u16);
+
+        Ok(())
     }
 
-    #[inline(always)]
-    fn ret(&mut self, mem: &mut Memory) {
-        self.dec_sp();
+    fn pop_op(&mut self, mem: &Memory) -> Result<u16> {
+        let sp = self.sp.checked_sub(STACK_ENTRY_SIZE as u16);
+        if sp.is_none_or(|sp| sp < self.stack_base) {
+            return Err(Error::StackUnderflow { sp: self.sp, pc: self.pc });
+        }
 
+        self.dec_sp();
         let ll = mem[self.sp as usize];
-        let hh = mem[(self.sp + 1) as usize];
+        let hh = mem[self.sp as usize + 1];
 
-        self.pc = little_endian!(ll, hh) as u16;
+        Ok(little_endian!(ll, hh))
+    }
+
+    fn call_hhll(&mut self, ll: u8, hh: u8, mem: &mut Memory) -> Result<()> {
+        self.push_op(self.pc, mem)?;
+        self.jmp(little_endian!(ll, hh));
+        Ok(())
+    }
+
+    fn call(&mut self, x: u8, mem: &mut Memory) -> Result<()> {
+        self.push_op(self.pc, mem)?;
+        self.jmp(self.r[x as usize] as u16);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn ret(&mut self, mem: &mut Memory) -> Result<()> {
+        self.pc = self.pop_op(mem)?;
         self.inc_pc();
+        Ok(())
     }
 
     #[inline(always)]
@@ -181,24 +330,58 @@ impl Cpu {
         }
     }
 
+    /// Evaluates the condition code `x` of Jx and Cx against the flags.
+    fn condition(&mut self, x: u8) -> Result<bool> {
+        let jmp_type = JMP_TYPE::from_u8(x).ok_or(Error::InvalidCondition {
+            condition: x,
+            pc: self.pc,
+        })?;
+
+        Ok(match jmp_type {
+            JMP_TYPE::Z => self.flags.z(),
+            JMP_TYPE::NZ => !self.flags.z(),
+            JMP_TYPE::N => self.flags.n(),
+            JMP_TYPE::NN => !self.flags.n(),
+            JMP_TYPE::P => !self.flags.n() && !self.flags.z(),
+            JMP_TYPE::O => self.flags.o(),
+            JMP_TYPE::NO => !self.flags.o(),
+            JMP_TYPE::A => !self.flags.c() && !self.flags.z(),
+            JMP_TYPE::AE => !self.flags.c(),
+            JMP_TYPE::B => self.flags.c(),
+            JMP_TYPE::BE => self.flags.c() || self.flags.z(),
+            JMP_TYPE::G => !self.flags.z() && (self.flags.n() == self.flags.o()),
+            JMP_TYPE::GE => self.flags.n() == self.flags.o(),
+            JMP_TYPE::L => self.flags.n() != self.flags.o(),
+            JMP_TYPE::LE => self.flags.z() || (self.flags.n() != self.flags.o()),
+        })
+    }
+
     #[inline(always)]
-    fn jx(&mut self, x: u8, ll: u8, hh: u8) {
-        let jmp_type = JMP_TYPE::from_u8(x).unwrap_or_else(|| {
-           panic!("Unrecognized JMP Type: {:#04x}", x);
-        });
-
-        match jmp_type {
-            JMP_TYPE::Z => if self.flags.z() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::NZ => if !self.flags.z() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::N => if self.flags.z() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::NN => if !self.flags.n() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::P => if !self.flags.n() && !self.flags.z() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::A => if !self.flags.c() && !self.flags.z() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::AE => if !self.flags.c() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::B => if self.flags.c() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::BE => if self.flags.c() || self.flags.z() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::LE => if self.flags.z() || (self.flags.n() != self.flags.o()) { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
-            JMP_TYPE::GE => if self.flags.n() == self.flags.o() { self.jmp(little_endian!(ll, hh)) } else { self.inc_pc() },
+    fn jx(&mut self, x: u8, ll: u8, hh: u8) -> Result<()> {
+        if self.condition(x)? {
+            self.jmp(little_endian!(ll, hh));
+        } else {
+            self.inc_pc();
+        }
+
+        Ok(())
+    }
+
+    /// JMC: deprecated jump if carry, same as JB.
+    fn jmc(&mut self, ll: u8, hh: u8) {
+        if self.flags.c() {
+            self.jmp(little_endian!(ll, hh));
+        } else {
+            self.inc_pc();
+        }
+    }
+
+    fn cx(&mut self, x: u8, ll: u8, hh: u8, mem: &mut Memory) -> Result<()> {
+        if self.condition(x)? {
+            self.call_hhll(ll, hh, mem)
+        } else {
+            self.inc_pc();
+            Ok(())
         }
     }
 
@@ -208,9 +391,9 @@ impl Cpu {
     }
 
     fn ldm_r(&mut self, x: u8, y: u8, mem: &mut Memory) {
-        let src = (self.r[y as usize] as u16 & 0xffff) as usize;
+        let src = self.r[y as usize] as u16 as usize;
         let res_ll = mem[src];
-        let res_hh = mem[src+1];
+        let res_hh = mem[(src + 1) & 0xffff];
 
         self.r[x as usize] = little_endian!(res_ll, res_hh) as i16;
         self.inc_pc();
@@ -219,24 +402,25 @@ impl Cpu {
     fn ldm_hhll(&mut self, x: u8, ll: u8, hh: u8, mem: &mut Memory) {
         let src = little_endian!(ll, hh) as usize;
         let res_ll = mem[src];
-        let res_hh = mem[src+1];
+        let res_hh = mem[(src + 1) & 0xffff];
 
         self.r[x as usize] = little_endian!(res_ll, res_hh) as i16;
         self.inc_pc();
     }
 
-    fn stm_op(&mut self, val: u16, dst: usize, mem: &mut Memory) {
-        mem[dst] = (val & 0x00ff) as u8;
-        mem[dst+1] = ((val & 0xff00) >> 8) as u8;
+    /// Stores `val` little-endian at `dst`, the high byte wraps around to 0x0000.
+    fn stm_op(&mut self, val: u16, dst: u16, mem: &mut Memory) {
+        mem[dst as usize] = (val & 0x00ff) as u8;
+        mem[dst.wrapping_add(1) as usize] = ((val & 0xff00) >> 8) as u8;
     }
 
     fn stm(&mut self, x: u8, ll: u8, hh: u8, mem: &mut Memory) {
-        self.stm_op(self.r[x as usize] as u16, little_endian!(ll, hh) as usize, mem);
+        self.stm_op(self.r[x as usize] as u16, little_endian!(ll, hh), mem);
         self.inc_pc()
     }
 
     fn stm_xy(&mut self, x: u8, y: u8, mem: &mut Memory) {
-        self.stm_op(self.r[x as usize] as u16, self.r[y as usize] as usize, mem);
+        self.stm_op(self.r[x as usize] as u16, self.r[y as usize] as u16, mem);
         self.inc_pc();
     }
 
@@ -321,7 +505,20 @@ impl Cpu {
         self.inc_pc();
     }
 
+    /// Result of dividing by zero, 0 with the zero flag set.
+    fn div_by_zero(&mut self) -> i16 {
+        self.flags.clear_c();
+        self.flags.check_n(0);
+        self.flags.check_z(0);
+
+        0
+    }
+
     fn div_op(&mut self, a: i16, b: i16) -> i16 {
+        if b == 0 {
+            return self.div_by_zero();
+        }
+
         let div = a.wrapping_div(b);
         let rem = a.wrapping_rem(b);
 
@@ -347,6 +544,70 @@ impl Cpu {
         self.inc_pc();
     }
 
+    fn div_xyz(&mut self, x: u8, y: u8, z: u8) {
+        self.r[z as usize] = self.div_op(self.r[x as usize], self.r[y as usize]);
+        self.inc_pc();
+    }
+
+    /// Modulus, the result takes the sign of the divisor.
+    fn mod_op(&mut self, a: i16, b: i16) -> i16 {
+        if b == 0 {
+            return self.div_by_zero();
+        }
+
+        let rem = a.wrapping_rem(b);
+        let md = if rem != 0 && (rem < 0) != (b < 0) { rem.wrapping_add(b) } else { rem };
+
+        self.flags.check_n(md);
+        self.flags.check_z(md);
+
+        md
+    }
+
+    fn modi(&mut self, x: u8, ll: u8, hh: u8) {
+        self.r[x as usize] = self.mod_op(self.r[x as usize], little_endian!(ll, hh) as i16);
+        self.inc_pc();
+    }
+
+    fn mod_xy(&mut self, x: u8, y: u8) {
+        self.r[x as usize] = self.mod_op(self.r[x as usize], self.r[y as usize]);
+        self.inc_pc();
+    }
+
+    fn mod_xyz(&mut self, x: u8, y: u8, z: u8) {
+        self.r[z as usize] = self.mod_op(self.r[x as usize], self.r[y as usize]);
+        self.inc_pc();
+    }
+
+    /// Remainder, the result takes the sign of the dividend.
+    fn rem_op(&mut self, a: i16, b: i16) -> i16 {
+        if b == 0 {
+            return self.div_by_zero();
+        }
+
+        let rem = a.wrapping_rem(b);
+
+        self.flags.check_n(rem);
+        self.flags.check_z(rem);
+
+        rem
+    }
+
+    fn remi(&mut self, x: u8, ll: u8, hh: u8) {
+        self.r[x as usize] = self.rem_op(self.r[x as usize], little_endian!(ll, hh) as i16);
+        self.inc_pc();
+    }
+
+    fn rem_xy(&mut self, x: u8, y: u8) {
+        self.r[x as usize] = self.rem_op(self.r[x as usize], self.r[y as usize]);
+        self.inc_pc();
+    }
+
+    fn rem_xyz(&mut self, x: u8, y: u8, z: u8) {
+        self.r[z as usize] = self.rem_op(self.r[x as usize], self.r[y as usize]);
+        self.inc_pc();
+    }
+
     fn add_op(&mut self, a: i16, b: i16) -> i16 {
         let sum = (a as u32  & 0xffff) + (b as u32 & 0xffff);
 
@@ -424,6 +685,11 @@ impl Cpu {
         xor
     }
 
+    fn xori(&mut self, x: u8, ll: u8, hh: u8) {
+        self.r[x as usize] = self.xor_op(self.r[x as usize], little_endian!(ll, hh) as i16);
+        self.inc_pc();
+    }
+
     fn xor_xy(&mut self, x: u8, y: u8) {
         self.r[x as usize] = self.xor_op(self.r[x as usize], self.r[y as usize]);
         self.inc_pc();
@@ -443,6 +709,11 @@ impl Cpu {
         or
     }
 
+    fn ori(&mut self, x: u8, ll: u8, hh: u8) {
+        self.r[x as usize] = self.or_op(self.r[x as usize], little_endian!(ll, hh) as i16);
+        self.inc_pc();
+    }
+
     fn or_xy(&mut self, x: u8, y: u8) {
         self.r[x as usize] = self.or_op(self.r[x as usize], self.r[y as usize]);
         self.inc_pc();
@@ -453,33 +724,45 @@ impl Cpu {
         self.inc_pc();
     }
 
-    fn pushf(&mut self, mem: &mut Memory) {
-        mem[self.sp as usize] = self.flags.into();
-        mem[self.sp as usize + 1] = 0;
-        self.inc_sp();
+    fn pushf(&mut self, mem: &mut Memory) -> Result<()> {
+        let flags: u8 = self.flags.into();
+        self.push_op(flags as u16, mem)?;
         self.inc_pc();
+        Ok(())
     }
 
-    fn pop(&mut self, x: u8, mem: &mut Memory) {
-        self.dec_sp();
-        let addr = self.sp as usize;
-
-        let ll = mem[addr];
-        let hh = mem[addr + 1];
+    fn popf(&mut self, mem: &mut Memory) -> Result<()> {
+        self.flags = CpuFlags(self.pop_op(mem)? as u8);
+        self.inc_pc();
+        Ok(())
+    }
 
-        self.r[x as usize] = little_endian!(ll, hh) as i16;
+    fn pushall(&mut self, mem: &mut Memory) -> Result<()> {
+        for i in 0..self.r.len() {
+            self.push_op(self.r[i] as u16, mem)?;
+        }
         self.inc_pc();
+        Ok(())
     }
 
-    fn push(&mut self, x: u8, mem: &mut Memory) {
-        let addr = self.sp as usize;
-        let val = self.r[x as usize] as u16;
+    fn popall(&mut self, mem: &mut Memory) -> Result<()> {
+        for i in (0..self.r.len()).rev() {
+            self.r[i] = self.pop_op(mem)? as i16;
+        }
+        self.inc_pc();
+        Ok(())
+    }
 
-        mem[addr] = (val & 0x00ff) as u8;
-        mem[addr + 1] = ((val & 0xff00) >> 8) as u8;
+    fn pop(&mut self, x: u8, mem: &mut Memory) -> Result<()> {
+        self.r[x as usize] = self.pop_op(mem)? as i16;
+        self.inc_pc();
+        Ok(())
+    }
 
-        self.inc_sp();
+    fn push(&mut self, x: u8, mem: &mut Memory) -> Result<()> {
+        self.push_op(self.r[x as usize] as u16, mem)?;
         self.inc_pc();
+        Ok(())
     }
 
     fn shr_op(&mut self, x: i16, n: u8) -> i16 {
@@ -521,23 +804,78 @@ impl Cpu {
         self.r[x as usize] = self.shl_op(self.r[x as usize], self.r[y as usize] as u8);
         self.inc_pc();
     }
+
+    fn shr_xy(&mut self, x: u8, y: u8) {
+        self.shr(x, self.r[y as usize] as u8);
+    }
+
+    fn sar_xy(&mut self, x: u8, y: u8) {
+        self.sar(x, self.r[y as usize] as u8);
+    }
+
+    fn not_op(&mut self, a: i16) -> i16 {
+        let not = !a;
+
+        self.flags.check_z(not);
+        self.flags.check_n(not);
+
+        not
+    }
+
+    fn noti(&mut self, x: u8, ll: u8, hh: u8) {
+        self.r[x as usize] = self.not_op(little_endian!(ll, hh) as i16);
+        self.inc_pc();
+    }
+
+    fn not_xy(&mut self, x: u8, y: u8) {
+        self.r[x as usize] = self.not_op(self.r[y as usize]);
+        self.inc_pc();
+    }
+
+    fn neg_op(&mut self, a: i16) -> i16 {
+        let neg = a.wrapping_neg();
+
+        self.flags.check_z(neg);
+        self.flags.check_n(neg);
+
+        neg
+    }
+
+    fn negi(&mut self, x: u8, ll: u8, hh: u8) {
+        self.r[x as usize] = self.neg_op(little_endian!(ll, hh) as i16);
+        self.inc_pc();
+    }
+
+    fn neg_xy(&mut self, x: u8, y: u8) {
+        self.r[x as usize] = self.neg_op(self.r[y as usize]);
+        self.inc_pc();
+    }
 }
 
 impl Default for Cpu {
     fn default() -> Self {
         Cpu {
-            sp: 0xfdf0,
+            sp: STACK_START,
+            stack_base: STACK_START,
             pc: 0,
             r: [0; 16],
             flags: CpuFlags::default(),
+            seed: 0,
+            rng: Rng(0),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::cpu::{Cpu, INSTRUCTION_SIZE, STACK_ENTRY_SIZE};
+    use crate::cpu::{Cpu, INSTRUCTION_SIZE, STACK_ENTRY_SIZE, STACK_END};
     use crate::memory::Memory;
+    use crate::error::Error;
+    use crate::screen::Screen;
+    use crate::sound::Sound;
+    use crate::surface::TestSurface;
+    use crate::audio::NullAudioSink;
+    use crate::flags::CpuFlags;
 
     #[test]
     fn test_inc_pc() {
@@ -586,6 +924,17 @@ mod tests {
         cpu.stm(0, 0xaa, 0xaa, &mut mem);
         assert_eq!(mem[0xaaaa], 0xad);
         assert_eq!(mem[0xaaab], 0xde);
+
+        // The high byte wraps around the address space.
+        cpu.stm(0, 0xff, 0xff, &mut mem);
+        assert_eq!(mem[0xffff], 0xad);
+        assert_eq!(mem[0x0000], 0xde);
+
+        cpu.r[0] = 0x1234;
+        cpu.r[1] = -1;
+        cpu.stm_xy(0, 1, &mut mem);
+        assert_eq!(mem[0xffff], 0x34);
+        assert_eq!(mem[0x0000], 0x12);
     }
     #[test]
     fn test_call_hhll() {
@@ -593,7 +942,7 @@ mod tests {
         cpu.pc = 0xffee;
 
         let mut mem = Memory::default();
-        cpu.call_hhll(0xad, 0xde, &mut mem);
+        cpu.call_hhll(0xad, 0xde, &mut mem).unwrap();
 
         assert_eq!(cpu.pc, 0xdead);
         assert_eq!(cpu.sp, (0xfdf0 + STACK_ENTRY_SIZE) as u16);
@@ -608,7 +957,7 @@ mod tests {
         cpu.r[0] = -8531;
 
         let mut mem = Memory::default();
-        cpu.call(0, &mut mem);
+        cpu.call(0, &mut mem).unwrap();
 
         assert_eq!(cpu.pc, 0xdead);
         assert_eq!(cpu.sp, (0xfdf0 + STACK_ENTRY_SIZE) as u16);
@@ -622,10 +971,10 @@ mod tests {
         cpu.pc = 0xffee;
 
         let mut mem = Memory::default();
-        cpu.call_hhll(0xad, 0xde, &mut mem);
+        cpu.call_hhll(0xad, 0xde, &mut mem).unwrap();
         assert_eq!(cpu.pc, 0xdead);
 
-        cpu.ret(&mut mem);
+        cpu.ret(&mut mem).unwrap();
         assert_eq!(cpu.pc, 0xffee + INSTRUCTION_SIZE as u16);
     }
 
@@ -644,21 +993,21 @@ mod tests {
         let mut cpu = Cpu::default();
 
         cpu.pc = 0xffee;
-        cpu.jx(0,0xad, 0xde);
+        cpu.jx(0,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xffee + INSTRUCTION_SIZE as u16);
 
         cpu.flags.set_z();
-        cpu.jx(0,0xad, 0xde);
+        cpu.jx(0,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xdead);
 
         let mut cpu = Cpu::default();
 
         cpu.pc = 0xffee;
-        cpu.jx(0xe,0xad, 0xde);
+        cpu.jx(0xe,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xffee + INSTRUCTION_SIZE as u16);
 
         cpu.flags.set_n();
-        cpu.jx(0xe,0xad, 0xde);
+        cpu.jx(0xe,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xdead);
     }
 
@@ -667,11 +1016,11 @@ mod tests {
         let mut cpu = Cpu::default();
 
         cpu.pc = 0xffee;
-        cpu.jx(0xe,0xad, 0xde);
+        cpu.jx(0xe,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xffee + INSTRUCTION_SIZE as u16);
 
         cpu.flags.set_z();
-        cpu.jx(0xe,0xad, 0xde);
+        cpu.jx(0xe,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xdead);
     }
 
@@ -681,13 +1030,13 @@ mod tests {
 
         cpu.pc = 0xffee;
         cpu.flags.set_n();
-        cpu.jx(0xc,0xad, 0xde);
+        cpu.jx(0xc,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xffee + INSTRUCTION_SIZE as u16);
 
         cpu.pc = 0xffee;
         cpu.flags.clear_n();
         cpu.pc = 0xffee;
-        cpu.jx(0xc,0xad, 0xde);
+        cpu.jx(0xc,0xad, 0xde).unwrap();
         assert_eq!(cpu.pc, 0xdead);
 
     }
@@ -719,6 +1068,12 @@ mod tests {
 
         cpu.ldm_r(0, 1, &mut mem);
         assert_eq!(cpu.r[0], -8531);
+
+        mem[0xffff] = 0x34;
+        mem[0x0000] = 0x12;
+        cpu.r[1] = -1;
+        cpu.ldm_r(0, 1, &mut mem);
+        assert_eq!(cpu.r[0], 0x1234);
     }
 
     #[test]
@@ -740,6 +1095,11 @@ mod tests {
         mem[0xfffb] = 0xde;
         cpu.ldm_hhll(0, 0xfa, 0xff, &mut mem);
         assert_eq!(cpu.r[0], -8531);
+
+        mem[0xffff] = 0x34;
+        mem[0x0000] = 0x12;
+        cpu.ldm_hhll(0, 0xff, 0xff, &mut mem);
+        assert_eq!(cpu.r[0], 0x1234);
     }
 
     #[test]
@@ -964,7 +1324,7 @@ mod tests {
         cpu.flags.set_n();
 
         let old_sp = cpu.sp;
-        cpu.pushf(&mut mem);
+        cpu.pushf(&mut mem).unwrap();
 
         assert_eq!(cpu.sp, old_sp + STACK_ENTRY_SIZE as u16);
         assert_eq!(mem[old_sp as usize], 0b11000110);
@@ -978,7 +1338,7 @@ mod tests {
         mem[cpu.sp as usize + 1] = 0xde;
         cpu.sp += STACK_ENTRY_SIZE as u16;
 
-        cpu.pop(0, &mut mem);
+        cpu.pop(0, &mut mem).unwrap();
         assert_eq!(cpu.r[0], -8531);
     }
 
@@ -988,18 +1348,18 @@ mod tests {
         let mut mem = Memory::default();
         cpu.r[0] = 42;
 
-        cpu.push(0, &mut mem);
+        cpu.push(0, &mut mem).unwrap();
         assert_eq!(mem[cpu.sp as usize - STACK_ENTRY_SIZE], 42);
 
         cpu.r[0] = 3;
-        cpu.push(0, &mut mem);
+        cpu.push(0, &mut mem).unwrap();
         assert_eq!(mem[cpu.sp as usize - STACK_ENTRY_SIZE], 3);
         assert_eq!(mem[cpu.sp as usize - STACK_ENTRY_SIZE + 1], 0);
 
         cpu.r[0] = 3;
         cpu.r[1] = 0;
-        cpu.push(0, &mut mem);
-        cpu.pop(1, &mut mem);
+        cpu.push(0, &mut mem).unwrap();
+        cpu.pop(1, &mut mem).unwrap();
         assert_eq!(cpu.r[1], 3);
     }
 
@@ -1072,4 +1432,300 @@ mod tests {
         assert!(!cpu.flags.z());
         assert!(!cpu.flags.n());
     }
+
+    #[test]
+    fn test_jx_invalid_condition() {
+        let mut cpu = Cpu::default();
+        cpu.pc = 0xffee;
+
+        match cpu.jx(0xf, 0xad, 0xde) {
+            Err(Error::InvalidCondition { condition: 0xf, pc: 0xffee }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::default();
+
+        cpu.sp = STACK_END - STACK_ENTRY_SIZE as u16;
+        cpu.push(0, &mut mem).unwrap();
+        assert_eq!(cpu.sp, STACK_END);
+
+        match cpu.push(0, &mut mem) {
+            Err(Error::StackOverflow { sp: STACK_END, .. }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match cpu.call_hhll(0xad, 0xde, &mut mem) {
+            Err(Error::StackOverflow { .. }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(mem[STACK_END as usize], 0);
+
+        cpu.sp = 0xffff;
+        match cpu.push(0, &mut mem) {
+            Err(Error::StackOverflow { sp: 0xffff, .. }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::default();
+
+        match cpu.pop(0, &mut mem) {
+            Err(Error::StackUnderflow { sp: 0xfdf0, .. }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match cpu.ret(&mut mem) {
+            Err(Error::StackUnderflow { .. }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_ldi_sp() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::default();
+        let mut screen = Screen::<TestSurface>::new();
+        let mut sound = Sound::new(NullAudioSink);
+
+        // LDI SP, 0x1000; PUSH R0; POP R1
+        let program = [0x21, 0x00, 0x00, 0x10, 0xc0, 0x00, 0x00, 0x00, 0xc1, 0x01, 0x00, 0x00];
+        for (i, byte) in program.iter().enumerate() {
+            mem[i] = *byte;
+        }
+        cpu.r[0] = -8531;
+
+        cpu.exec_instruction(&mut mem, &mut screen, &mut sound).unwrap();
+        assert_eq!(cpu.sp, 0x1000);
+        cpu.exec_instruction(&mut mem, &mut screen, &mut sound).unwrap();
+        assert_eq!(cpu.sp, 0x1002);
+        assert_eq!(mem[0x1000], 0xad);
+        assert_eq!(mem[0x1001], 0xde);
+        cpu.exec_instruction(&mut mem, &mut screen, &mut sound).unwrap();
+        assert_eq!(cpu.sp, 0x1000);
+        assert_eq!(cpu.r[1], -8531);
+
+        // The moved stack is empty again.
+        match cpu.pop(1, &mut mem) {
+            Err(Error::StackUnderflow { sp: 0x1000, .. }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_exec_wraps_around() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::default();
+        let mut screen = Screen::<TestSurface>::new();
+        let mut sound = Sound::new(NullAudioSink);
+
+        // NOP at 0xFFFC, then LDI R0, 42 split over 0xFFFE-0x0001.
+        mem[0xfffe] = 0x20;
+        mem[0x0000] = 0x2a;
+
+        cpu.pc = 0xfffc;
+        cpu.exec_instruction(&mut mem, &mut screen, &mut sound).unwrap();
+        assert_eq!(cpu.pc, 0x0000);
+
+        cpu.pc = 0xfffe;
+        cpu.exec_instruction(&mut mem, &mut screen, &mut sound).unwrap();
+        assert_eq!(cpu.r[0], 42);
+        assert_eq!(cpu.pc, 0x0002);
+    }
+
+    #[test]
+    fn test_exec_instruction() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::default();
+        let mut screen = Screen::<TestSurface>::new();
+        let mut sound = Sound::new(NullAudioSink);
+
+        mem[0x0] = 0x20;
+        mem[0x2] = 0x2a;
+        mem[0x4] = 0xff;
+
+        cpu.exec_instruction(&mut mem, &mut screen, &mut sound).unwrap();
+        assert_eq!(cpu.r[0], 42);
+        assert_eq!(cpu.pc, 4);
+
+        match cpu.exec_instruction(&mut mem, &mut screen, &mut sound) {
+            Err(Error::UnknownOpcode { opcode: 0xff, pc: 4 }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_jx_conditions() {
+        let cases = vec![
+            // (condition, flags, taken)
+            (0x2, 0x80, true),
+            (0x2, 0x04, false),
+            (0x5, 0x40, true),
+            (0x6, 0x40, false),
+            (0xb, 0x00, true),
+            (0xb, 0x04, false),
+            (0xb, 0x80, false),
+            (0xd, 0x80, true),
+            (0xd, 0xc0, false),
+        ];
+
+        for (condition, flags, taken) in cases {
+            let mut cpu = Cpu { flags: CpuFlags(flags), pc: 0xffee, ..Cpu::default() };
+            cpu.jx(condition, 0xad, 0xde).unwrap();
+            assert_eq!(cpu.pc == 0xdead, taken, "condition {:X}, flags {:08b}", condition, flags);
+        }
+    }
+
+    #[test]
+    fn test_cx() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::default();
+        cpu.pc = 0x0100;
+
+        cpu.cx(0x0, 0xad, 0xde, &mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0104);
+        assert_eq!(cpu.sp, 0xfdf0);
+
+        cpu.flags.set_z();
+        cpu.cx(0x0, 0xad, 0xde, &mut mem).unwrap();
+        assert_eq!(cpu.pc, 0xdead);
+        assert_eq!(cpu.sp, 0xfdf2);
+    }
+
+    #[test]
+    fn test_mod_rem() {
+        let mut cpu = Cpu::default();
+
+        let cases = vec![
+            // (a, b, mod, rem)
+            (7, 3, 1, 1),
+            (-7, 3, 2, -1),
+            (7, -3, -2, 1),
+            (-7, -3, -1, -1),
+            (6, 3, 0, 0),
+        ];
+
+        for (a, b, md, rem) in cases {
+            cpu.r[0] = a;
+            cpu.r[1] = b;
+            cpu.mod_xyz(0, 1, 2);
+            assert_eq!(cpu.r[2], md);
+            cpu.rem_xyz(0, 1, 3);
+            assert_eq!(cpu.r[3], rem);
+        }
+
+        assert!(cpu.flags.z());
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+        let mut cpu = Cpu::default();
+        cpu.r[0] = 5;
+        cpu.r[1] = 3;
+        cpu.div_xy(0, 1);
+        assert!(cpu.flags.c());
+
+        cpu.divi(0, 0x00, 0x00);
+        assert_eq!(cpu.r[0], 0);
+        assert!(!cpu.flags.c());
+        assert!(cpu.flags.z());
+        assert!(!cpu.flags.n());
+    }
+
+    #[test]
+    fn test_mod_by_zero() {
+        let mut cpu = Cpu::default();
+        cpu.r[0] = -7;
+        cpu.r[1] = 0;
+
+        cpu.mod_xyz(0, 1, 2);
+        assert_eq!(cpu.r[2], 0);
+        assert!(cpu.flags.z());
+        assert!(!cpu.flags.n());
+
+        cpu.modi(0, 0x00, 0x00);
+        assert_eq!(cpu.r[0], 0);
+        assert!(cpu.flags.z());
+    }
+
+    #[test]
+    fn test_rem_by_zero() {
+        let mut cpu = Cpu::default();
+        cpu.r[0] = -7;
+        cpu.r[1] = 0;
+
+        cpu.rem_xyz(0, 1, 2);
+        assert_eq!(cpu.r[2], 0);
+        assert!(cpu.flags.z());
+        assert!(!cpu.flags.n());
+
+        cpu.remi(0, 0x00, 0x00);
+        assert_eq!(cpu.r[0], 0);
+        assert!(cpu.flags.z());
+    }
+
+    #[test]
+    fn test_not_neg() {
+        let mut cpu = Cpu::default();
+
+        cpu.noti(0, 0xff, 0x00);
+        assert_eq!(cpu.r[0], -256);
+        assert!(cpu.flags.n());
+
+        cpu.neg_xy(1, 0);
+        assert_eq!(cpu.r[1], 256);
+        assert!(!cpu.flags.n());
+
+        cpu.not_xy(1, 1);
+        assert_eq!(cpu.r[1], -257);
+
+        cpu.negi(2, 0x00, 0x00);
+        assert_eq!(cpu.r[2], 0);
+        assert!(cpu.flags.z());
+    }
+
+    #[test]
+    fn test_pushall_popall() {
+        let mut cpu = Cpu::default();
+        let mut mem = Memory::default();
+        for i in 0..16 {
+            cpu.r[i] = i as i16 * 3 - 8;
+        }
+        let r = cpu.r;
+
+        cpu.pushall(&mut mem).unwrap();
+        assert_eq!(cpu.sp, 0xfdf0 + 32);
+
+        cpu.r = [0; 16];
+        cpu.popall(&mut mem).unwrap();
+        assert_eq!(cpu.r, r);
+        assert_eq!(cpu.sp, 0xfdf0);
+
+        cpu.flags = CpuFlags(0xc6);
+        cpu.pushf(&mut mem).unwrap();
+        cpu.flags = CpuFlags(0);
+        cpu.popf(&mut mem).unwrap();
+        assert_eq!(cpu.flags.0, 0xc6);
+    }
+
+    #[test]
+    fn test_rnd() {
+        let mut cpu = Cpu::default();
+        cpu.set_seed(7);
+
+        let numbers: Vec<i16> = (0..16).map(|_| { cpu.rnd(3, 0x10, 0x00); cpu.r[3] }).collect();
+        assert!(numbers.iter().all(|n| (0..=0x10).contains(n)));
+
+        // The same seed draws the same numbers.
+        cpu.set_seed(7);
+        for n in numbers {
+            cpu.rnd(3, 0x10, 0x00);
+            assert_eq!(cpu.r[3], n);
+        }
+        assert_eq!(cpu.seed(), 7);
+    }
 }
@@ -0,0 +1,47 @@
+/// CRC-32 (IEEE 802.3) as used by the CH16 ROM header.
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x1 > 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    update(0, data)
+}
+
+/// Continues a checksum over more data.
+pub fn update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc = TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crc32::{crc32, update};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(update(crc32(b"1234"), b"56789"), 0xcbf43926);
+    }
+}
@@ -0,0 +1,495 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::convert::TryInto;
+use crate::{FrameTimer, Rusty16};
+use crate::audio::AudioSink;
+use crate::cpu::INSTRUCTION_SIZE;
+use crate::error::Result;
+use crate::instruction::Instruction;
+use crate::opcode::Opcode;
+use crate::surface::Surface;
+
+const HELP: &str = "Commands:
+    b, break [ADDR]        Set a breakpoint at ADDR, list breakpoints without ADDR
+    d, delete ADDR         Clear the breakpoint at ADDR
+    s, step [N]            Execute N instructions, 1 by default
+    n, next                Step over CALL instructions
+    bs, back [N]           Step back N instructions, needs rewind history
+    bw, backwatch REG      Step back to the last instruction which changed REG (R0-RF, SP, FLAGS)
+    c, continue            Run until a breakpoint is hit
+    f, frame               Run until the end of the current frame
+    r, regs                Print PC, SP, registers and flags
+    x, dump ADDR [LEN]     Dump LEN bytes of memory, 64 by default
+    w, write ADDR BYTE...  Write bytes to memory
+    l, list [ADDR] [N]     Disassemble N instructions around ADDR, PC by default
+    h, help                Print this help
+    q, quit                Exit
+
+Numbers are decimal or 0x prefixed hexadecimal. An empty line repeats the last command.";
+
+/// Why the emulator stopped running.
+enum Stop {
+    Done,
+    Breakpoint,
+    Quit,
+}
+
+/// Interactive command-line debugger driving a `Rusty16`.
+pub struct Debugger<'e, 'a, S: Surface, A: AudioSink> {
+    emulator: &'e mut Rusty16<'a, S, A>,
+    breakpoints: BTreeSet<u16>,
+    last_command: String,
+}
+
+impl<'e, 'a, S: Surface, A: AudioSink> Debugger<'e, 'a, S, A> {
+    pub fn new(emulator: &'e mut Rusty16<'a, S, A>) -> Self {
+        Debugger {
+            emulator,
+            breakpoints: BTreeSet::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn breakpoint(&mut self, addr: u16) -> &mut Self {
+        self.breakpoints.insert(addr);
+        self
+    }
+
+    /// Loads the ROM and reads commands from `input` until it ends or `quit` is entered.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> Result<()> {
+        self.emulator.init()?;
+
+        writeln!(output, "Type 'help' for a list of commands.")?;
+        self.print_current(&mut output)?;
+
+        let mut lines = input.lines();
+        loop {
+            write!(output, "(rusty16) ")?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => return Ok(()),
+            };
+
+            if !self.exec(&line, &mut output)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes one command, returns `false` once the debugger should exit.
+    pub fn exec<W: Write>(&mut self, line: &str, output: &mut W) -> io::Result<bool> {
+        let line = if line.trim().is_empty() { self.last_command.clone() } else { line.trim().to_string() };
+        self.last_command = line.clone();
+
+        let mut args = line.split_whitespace();
+        let command = match args.next() {
+            Some(command) => command,
+            None => return Ok(true),
+        };
+        let args: Vec<&str> = args.collect();
+
+        let result = match command {
+            "b" | "break" => self.cmd_break(&args, output),
+            "d" | "delete" => self.cmd_delete(&args, output),
+            "s" | "step" => self.cmd_step(&args, output),
+            "n" | "next" => self.cmd_next(output),
+            "bs" | "back" => self.cmd_back(&args, output),
+            "bw" | "backwatch" => self.cmd_backwatch(&args, output),
+            "c" | "continue" => self.cmd_continue(output),
+            "f" | "frame" => self.cmd_frame(output),
+            "r" | "regs" => self.print_regs(output).map_err(|err| err.to_string()),
+            "x" | "dump" => self.cmd_dump(&args, output),
+            "w" | "write" => self.cmd_write(&args),
+            "l" | "list" => self.cmd_list(&args, output),
+            "h" | "help" => writeln!(output, "{}", HELP).map_err(|err| err.to_string()),
+            "q" | "quit" => return Ok(false),
+            _ => Err(format!("Unknown command: {}, type 'help' for a list of commands", command)),
+        };
+
+        if let Err(err) = result {
+            writeln!(output, "{}", err)?;
+        }
+
+        Ok(!self.emulator.quit_requested())
+    }
+
+    fn cmd_break<W: Write>(&mut self, args: &[&str], output: &mut W) -> std::result::Result<(), String> {
+        match args {
+            [] => {
+                for addr in self.breakpoints.iter() {
+                    writeln!(output, "0x{:04X}", addr).map_err(|err| err.to_string())?;
+                }
+            },
+            [addr] => {
+                self.breakpoints.insert(parse_addr(addr)?);
+            },
+            _ => return Err(String::from("Usage: break [ADDR]")),
+        }
+
+        Ok(())
+    }
+
+    fn cmd_delete<W: Write>(&mut self, args: &[&str], output: &mut W) -> std::result::Result<(), String> {
+        let addr = match args {
+            [addr] => parse_addr(addr)?,
+            _ => return Err(String::from("Usage: delete ADDR")),
+        };
+
+        if !self.breakpoints.remove(&addr) {
+            writeln!(output, "No breakpoint at 0x{:04X}", addr).map_err(|err| err.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn cmd_step<W: Write>(&mut self, args: &[&str], output: &mut W) -> std::result::Result<(), String> {
+        let n = match args {
+            [] => 1,
+            [n] => parse_number(n)?,
+            _ => return Err(String::from("Usage: step [N]")),
+        };
+
+        for _ in 0..n {
+            self.emulator.step().map_err(|err| err.to_string())?;
+            if self.emulator.quit_requested() {
+                break;
+            }
+        }
+
+        self.print_current(output).map_err(|err| err.to_string())
+    }
+
+    /// Steps over calls by running until the instruction after them.
+    fn cmd_next<W: Write>(&mut self, output: &mut W) -> std::result::Result<(), String> {
+        let pc = self.emulator.cpu().pc();
+        match Instruction(&self.emulator.memory().instruction_bytes(pc)).opcode() {
+            Some(Opcode::CALL_HHLL) | Some(Opcode::CALL) | Some(Opcode::CX) => {
+                let next = pc.wrapping_add(INSTRUCTION_SIZE as u16);
+                let stop = self.resume(|debugger| debugger.emulator.cpu().pc() == next)?;
+                self.report(stop, output)
+            },
+            _ => self.cmd_step(&[], output),
+        }
+    }
+
+    fn cmd_back<W: Write>(&mut self, args: &[&str], output: &mut W) -> std::result::Result<(), String> {
+        let n = match args {
+            [] => 1,
+            [n] => parse_number(n)?,
+            _ => return Err(String::from("Usage: back [N]")),
+        };
+
+        for _ in 0..n {
+            if !self.emulator.step_back().map_err(|err| err.to_string())? {
+                writeln!(output, "No history to step back").map_err(|err| err.to_string())?;
+                break;
+            }
+        }
+
+        self.print_current(output).map_err(|err| err.to_string())
+    }
+
+    /// Steps back to the instruction which last wrote a register, e.g. to find what clobbered it.
+    fn cmd_backwatch<W: Write>(&mut self, args: &[&str], output: &mut W) -> std::result::Result<(), String> {
+        let reg = match args {
+            [reg] => parse_register(reg)?,
+            _ => return Err(String::from("Usage: backwatch REG")),
+        };
+
+        let found = self.emulator.step_back_until(|before, after| match reg {
+            Register::R(i) => before.r()[i] != after.r()[i],
+            Register::Sp => before.sp() != after.sp(),
+            Register::Flags => before.flags() != after.flags(),
+        }).map_err(|err| err.to_string())?;
+
+        if !found {
+            writeln!(output, "No change of {} in the history", args[0]).map_err(|err| err.to_string())?;
+        }
+
+        self.print_current(output).map_err(|err| err.to_string())
+    }
+
+    /// Runs at normal speed until a breakpoint is hit, `done` returns `true` or the window is closed.
+    fn resume<F>(&mut self, done: F) -> std::result::Result<Stop, String>
+        where F: Fn(&Self) -> bool
+    {
+        let mut timer = FrameTimer::new();
+
+        loop {
+            self.emulator.step().map_err(|err| err.to_string())?;
+
+            if self.emulator.quit_requested() {
+                return Ok(Stop::Quit);
+            }
+            if self.breakpoints.contains(&self.emulator.cpu().pc()) {
+                return Ok(Stop::Breakpoint);
+            }
+            if done(self) {
+                return Ok(Stop::Done);
+            }
+            if self.emulator.frame_cycles == 0 {
+                timer.wait();
+            }
+        }
+    }
+
+    fn cmd_continue<W: Write>(&mut self, output: &mut W) -> std::result::Result<(), String> {
+        let stop = self.resume(|_| false)?;
+        self.report(stop, output)
+    }
+
+    fn cmd_frame<W: Write>(&mut self, output: &mut W) -> std::result::Result<(), String> {
+        match self.resume(|debugger| debugger.emulator.frame_cycles == 0)? {
+            Stop::Done => writeln!(output, "Frame {}", self.emulator.frame()).map_err(|err| err.to_string())?,
+            stop => return self.report(stop, output),
+        }
+
+        self.print_current(output).map_err(|err| err.to_string())
+    }
+
+    fn report<W: Write>(&self, stop: Stop, output: &mut W) -> std::result::Result<(), String> {
+        match stop {
+            Stop::Quit => return Ok(()),
+            Stop::Breakpoint => writeln!(output, "Breakpoint at 0x{:04X}", self.emulator.cpu().pc()).map_err(|err| err.to_string())?,
+            Stop::Done => (),
+        }
+
+        self.print_current(output).map_err(|err| err.to_string())
+    }
+
+    fn cmd_dump<W: Write>(&mut self, args: &[&str], output: &mut W) -> std::result::Result<(), String> {
+        let (addr, len) = match args {
+            [addr] => (parse_addr(addr)?, 64),
+            [addr, len] => (parse_addr(addr)?, parse_number(len)?),
+            _ => return Err(String::from("Usage: dump ADDR [LEN]")),
+        };
+        // Past 64K the dump would only repeat itself.
+        let len = len.min(0x10000);
+
+        let memory = self.emulator.memory();
+        for row in (0..len).step_by(16) {
+            let start = addr as usize + row;
+            let bytes: Vec<String> = (start..start + 16.min(len - row))
+                .map(|i| format!("{:02X}", memory[i & 0xffff]))
+                .collect();
+
+            writeln!(output, "0x{:04X}: {}", start & 0xffff, bytes.join(" ")).map_err(|err| err.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn cmd_write(&mut self, args: &[&str]) -> std::result::Result<(), String> {
+        let (addr, bytes) = match args {
+            [addr, bytes @ ..] if !bytes.is_empty() => (parse_addr(addr)?, bytes),
+            _ => return Err(String::from("Usage: write ADDR BYTE...")),
+        };
+
+        let bytes = bytes.iter()
+            .map(|byte| parse_number(byte).and_then(|b| b.try_into().map_err(|_| format!("Not a byte: {}", byte))))
+            .collect::<std::result::Result<Vec<u8>, _>>()?;
+
+        let memory = self.emulator.memory_mut();
+        for (i, byte) in bytes.into_iter().enumerate() {
+            memory[(addr as usize + i) & 0xffff] = byte;
+        }
+
+        Ok(())
+    }
+
+    fn cmd_list<W: Write>(&mut self, args: &[&str], output: &mut W) -> std::result::Result<(), String> {
+        let (addr, n) = match args {
+            [] => (self.emulator.cpu().pc(), 9),
+            [addr] => (parse_addr(addr)?, 9),
+            [addr, n] => (parse_addr(addr)?, parse_number(n)?),
+            _ => return Err(String::from("Usage: list [ADDR] [N]")),
+        };
+
+        let before = (n / 2).min(addr as usize / INSTRUCTION_SIZE);
+        let start = addr as usize - before * INSTRUCTION_SIZE;
+        for i in 0..n {
+            let addr = start + i * INSTRUCTION_SIZE;
+            if addr > 0xffff {
+                break;
+            }
+
+            self.print_instruction(addr as u16, output).map_err(|err| err.to_string())?;
+        }
+
+        Ok(())
+    }
+
+    fn print_regs<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let cpu = self.emulator.cpu();
+        let mut flags = cpu.flags();
+
+        writeln!(output, "PC: 0x{:04X}  SP: 0x{:04X}  Flags: C={} Z={} O={} N={}",
+                 cpu.pc(), cpu.sp(), flags.c() as u8, flags.z() as u8, flags.o() as u8, flags.n() as u8)?;
+
+        for (i, r) in cpu.r().iter().enumerate() {
+            let separator = if i % 4 == 3 { "\n" } else { "  " };
+            write!(output, "R{:X}: 0x{:04X}{}", i, *r as u16, separator)?;
+        }
+
+        writeln!(output, "Frame: {}  Cycles: {}", self.emulator.frame(), self.emulator.cycles())
+    }
+
+    fn print_current<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.print_instruction(self.emulator.cpu().pc(), output)
+    }
+
+    /// Prints the instruction at `addr`, marking the PC and breakpoints.
+    fn print_instruction<W: Write>(&self, addr: u16, output: &mut W) -> io::Result<()> {
+        let marker = if addr == self.emulator.cpu().pc() { "=>" } else { "  " };
+        let breakpoint = if self.breakpoints.contains(&addr) { "*" } else { " " };
+        let bytes = self.emulator.memory().instruction_bytes(addr);
+        let instruction = Instruction(&bytes);
+
+        writeln!(output, "{}{} 0x{:04X}: {:<16} {}", marker, breakpoint, addr, instruction, instruction.to_asm_str())
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+fn parse_number(text: &str) -> std::result::Result<usize, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+
+    parsed.map_err(|_| format!("Invalid number: {}", text))
+}
+
+/// A register watched by `backwatch`.
+enum Register {
+    R(usize),
+    Sp,
+    Flags,
+}
+
+fn parse_register(text: &str) -> std::result::Result<Register, String> {
+    let lower = text.to_ascii_lowercase();
+    match lower.as_str() {
+        "sp" => Ok(Register::Sp),
+        "flags" => Ok(Register::Flags),
+        _ => lower.strip_prefix('r')
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| usize::from_str_radix(digit, 16).ok())
+            .map(Register::R)
+            .ok_or_else(|| format!("Invalid register: {}", text)),
+    }
+}
+
+fn parse_addr(text: &str) -> std::result::Result<u16, String> {
+    parse_number(text)?.try_into().map_err(|_| format!("Invalid address: {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rusty16;
+    use crate::debugger::Debugger;
+    use crate::surface::TestSurface;
+    use crate::audio::NullAudioSink;
+
+    const PROGRAM: &[u8] = &[
+        0x20, 0x00, 0x05, 0x00, // 0x00: LDI R0, 5
+        0x14, 0x00, 0x10, 0x00, // 0x04: CALL 0x0010
+        0x40, 0x00, 0x01, 0x00, // 0x08: ADDI R0, 1
+        0x10, 0x00, 0x08, 0x00, // 0x0C: JMP 0x0008
+        0x20, 0x01, 0x07, 0x00, // 0x10: LDI R1, 7
+        0x15, 0x00, 0x00, 0x00, // 0x14: RET
+    ];
+
+    fn exec(debugger: &mut Debugger<TestSurface, NullAudioSink>, line: &str) -> String {
+        let mut output = Vec::new();
+        assert!(debugger.exec(line, &mut output).unwrap());
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_step_and_next() {
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(PROGRAM).init().unwrap();
+        let mut debugger = Debugger::new(&mut emulator);
+
+        assert!(exec(&mut debugger, "step").contains("=>  0x0004:"));
+        assert!(exec(&mut debugger, "n").contains("0x0008: "));
+        assert_eq!(debugger.emulator.cpu().r()[1], 7);
+        assert_eq!(debugger.emulator.cycles(), 4);
+
+        // An empty line repeats the last command.
+        exec(&mut debugger, "");
+        assert_eq!(debugger.emulator.cpu().pc(), 0x0c);
+        assert!(exec(&mut debugger, "s 3").contains("=>  0x0008: "));
+        assert_eq!(debugger.emulator.cpu().r()[0], 7);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(PROGRAM).init().unwrap();
+        let mut debugger = Debugger::new(&mut emulator);
+
+        exec(&mut debugger, "break 0x14");
+        exec(&mut debugger, "b 8");
+        assert_eq!(exec(&mut debugger, "break"), "0x0008\n0x0014\n");
+
+        assert!(exec(&mut debugger, "c").starts_with("Breakpoint at 0x0014\n"));
+        assert!(exec(&mut debugger, "continue").starts_with("Breakpoint at 0x0008\n"));
+
+        exec(&mut debugger, "delete 0x8");
+        exec(&mut debugger, "d 0x14");
+        assert!(exec(&mut debugger, "frame").starts_with("Frame 1\n"));
+        assert_eq!(debugger.emulator.frame(), 1);
+        assert_eq!(debugger.emulator.frame_cycles, 0);
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(PROGRAM).init().unwrap();
+        let mut debugger = Debugger::new(&mut emulator);
+
+        exec(&mut debugger, "write 0x1000 0xde 173 0xbe");
+        assert_eq!(exec(&mut debugger, "x 0x1000 4"), "0x1000: DE AD BE 00\n");
+        // Dumps wrap around the end of memory.
+        assert_eq!(exec(&mut debugger, "dump 0xfffe 3"), "0xFFFE: 00 00 20\n");
+        assert_eq!(exec(&mut debugger, "dump 0 0xffffffff").lines().count(), 0x1000);
+        assert_eq!(exec(&mut debugger, "w 0x1000 256"), "Not a byte: 256\n");
+
+        let listing = exec(&mut debugger, "list 0x10 3");
+        assert_eq!(listing.lines().count(), 3);
+        assert!(listing.contains("0x0010: 20  |1   |7   |0    LDI R1, 0x0007"));
+        // Instructions at the end of memory are decoded wrapping around.
+        exec(&mut debugger, "write 0xfffe 0x20 0x02");
+        let listing = exec(&mut debugger, "list 0xfffe 1");
+        assert!(listing.contains("0xFFFE: "), "{}", listing);
+        assert!(listing.contains("LDI R2, 0x0020"), "{}", listing);
+
+        let regs = exec(&mut debugger, "regs");
+        assert!(regs.starts_with("PC: 0x0000  SP: 0xFDF0"));
+    }
+
+    #[test]
+    fn test_back() {
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(PROGRAM).init().unwrap();
+        emulator.rewind(1);
+        let mut debugger = Debugger::new(&mut emulator);
+
+        assert!(exec(&mut debugger, "back").starts_with("No history to step back\n"));
+        exec(&mut debugger, "s 8");
+        assert_eq!(debugger.emulator.cpu().r()[0], 7);
+
+        assert!(exec(&mut debugger, "bs 2").contains("=>  0x0008: "));
+        assert_eq!(debugger.emulator.cycles(), 6);
+
+        // R1 was last written by the LDI in the called function.
+        assert!(exec(&mut debugger, "backwatch r1").contains("=>  0x0010: "));
+        assert_eq!(debugger.emulator.cycles(), 2);
+        assert!(exec(&mut debugger, "bw sp").contains("=>  0x0004: "));
+        assert!(exec(&mut debugger, "bw R2").starts_with("No change of R2 in the history\n"));
+        assert_eq!(exec(&mut debugger, "bw r16"), "Invalid register: r16\n");
+    }
+}
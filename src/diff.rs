@@ -0,0 +1,289 @@
+//! Finding the first instruction where two runs diverge, either from their traces
+//! or by running two machines in lockstep.
+
+use std::collections::VecDeque;
+use std::fmt;
+use crate::Rusty16;
+use crate::audio::AudioSink;
+use crate::error::Result;
+use crate::memory::MEMORY_SIZE;
+use crate::surface::Surface;
+use crate::trace::TraceRecord;
+
+/// The first diverging instruction of two runs.
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// Number of matching instructions before the divergence.
+    pub index: usize,
+    /// Matching records right before the divergence.
+    pub before: Vec<TraceRecord>,
+    /// The diverging record of each run followed by the next ones.
+    /// Empty if that run ended first.
+    pub left: Vec<TraceRecord>,
+    pub right: Vec<TraceRecord>,
+    /// Addresses and values of the bytes written by either diverging instruction
+    /// which differ afterwards. Only filled in lockstep, traces don't record memory.
+    pub memory: Vec<(u16, u8, u8)>,
+}
+
+impl Divergence {
+    /// Describes how the diverging records and memory differ.
+    pub fn differences(&self) -> Vec<String> {
+        let mut differences = Vec::new();
+
+        match (self.left.first(), self.right.first()) {
+            (Some(left), Some(right)) => {
+                if left.cycle != right.cycle {
+                    differences.push(format!("Cycle: {} != {}", left.cycle, right.cycle));
+                }
+                if left.pc != right.pc {
+                    differences.push(format!("PC: 0x{:04X} != 0x{:04X}", left.pc, right.pc));
+                }
+                if left.bytes != right.bytes {
+                    differences.push(format!("Instruction: {:02X?} != {:02X?}", left.bytes, right.bytes));
+                }
+                if left.sp != right.sp {
+                    differences.push(format!("SP: 0x{:04X} != 0x{:04X}", left.sp, right.sp));
+                }
+                if left.flags != right.flags {
+                    differences.push(format!("Flags: {:08b} != {:08b}", left.flags, right.flags));
+                }
+                for (i, (l, r)) in left.r.iter().zip(right.r.iter()).enumerate() {
+                    if l != r {
+                        differences.push(format!("R{:X}: 0x{:04X} != 0x{:04X}", i, *l as u16, *r as u16));
+                    }
+                }
+            },
+            (None, _) => differences.push(String::from("Left run ended")),
+            (_, None) => differences.push(String::from("Right run ended")),
+        }
+
+        for (addr, l, r) in self.memory.iter() {
+            differences.push(format!("Memory 0x{:04X}: 0x{:02X} != 0x{:02X}", addr, l, r));
+        }
+
+        differences
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Diverged after {} matching instructions", self.index)?;
+
+        for record in self.before.iter() {
+            writeln!(f, "  {}", record)?;
+        }
+
+        for difference in self.differences() {
+            writeln!(f, "! {}", difference)?;
+        }
+
+        for record in self.left.iter() {
+            writeln!(f, "- {}", record)?;
+        }
+        for record in self.right.iter() {
+            writeln!(f, "+ {}", record)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compares two traces record by record, keeping `context` records around the divergence.
+/// Both traces need to be recorded with the same filters.
+pub fn diff<L, R>(left: L, right: R, context: usize) -> Result<Option<Divergence>>
+    where L: IntoIterator<Item = Result<TraceRecord>>,
+          R: IntoIterator<Item = Result<TraceRecord>>
+{
+    let (mut left, mut right) = (left.into_iter(), right.into_iter());
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut index = 0;
+
+    loop {
+        let (l, r) = (left.next().transpose()?, right.next().transpose()?);
+        if l == r {
+            match l {
+                Some(record) => remember(&mut before, record, context),
+                None => return Ok(None),
+            }
+
+            index += 1;
+            continue;
+        }
+
+        return Ok(Some(Divergence {
+            index,
+            before: before.into_iter().collect(),
+            left: l.into_iter().map(Ok).chain(left.take(context)).collect::<Result<_>>()?,
+            right: r.into_iter().map(Ok).chain(right.take(context)).collect::<Result<_>>()?,
+            memory: Vec::new(),
+        }));
+    }
+}
+
+/// Steps two initialized machines side by side for up to `cycles` instructions,
+/// comparing the CPU state before and the memory writes of every instruction.
+/// Memory which differed from the start, like the ROMs, only counts once written.
+pub fn lockstep<S: Surface, A: AudioSink>(left: &mut Rusty16<'_, S, A>, right: &mut Rusty16<'_, S, A>,
+                                          cycles: u64, context: usize) -> Result<Option<Divergence>> {
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut left_memory = left.memory()[0..MEMORY_SIZE].to_vec();
+    let mut right_memory = right.memory()[0..MEMORY_SIZE].to_vec();
+
+    for index in 0..cycles as usize {
+        let (l, r) = (record(left), record(right));
+        if l != r {
+            // Report the state after the diverging instructions as well.
+            let (left_ok, right_ok) = (left.step().is_ok(), right.step().is_ok());
+
+            return Ok(Some(Divergence {
+                index,
+                before: before.into_iter().collect(),
+                left: Some(l).into_iter().chain(following(left, left_ok, context)).collect(),
+                right: Some(r).into_iter().chain(following(right, right_ok, context)).collect(),
+                memory: Vec::new(),
+            }));
+        }
+
+        left.step()?;
+        right.step()?;
+
+        let memory = write_differences(&left.memory()[0..MEMORY_SIZE], &right.memory()[0..MEMORY_SIZE],
+                                       &mut left_memory, &mut right_memory);
+        if !memory.is_empty() {
+            return Ok(Some(Divergence {
+                index,
+                before: before.into_iter().collect(),
+                left: Some(l).into_iter().chain(following(left, true, context)).collect(),
+                right: Some(r).into_iter().chain(following(right, true, context)).collect(),
+                memory,
+            }));
+        }
+
+        remember(&mut before, l, context);
+    }
+
+    Ok(None)
+}
+
+fn record<S: Surface, A: AudioSink>(emulator: &Rusty16<'_, S, A>) -> TraceRecord {
+    TraceRecord::new(emulator.cycles(), emulator.cpu(), emulator.memory())
+}
+
+/// Records up to `context` instructions starting at the current one, stops at the first error.
+fn following<S: Surface, A: AudioSink>(emulator: &mut Rusty16<'_, S, A>, ok: bool, context: usize) -> Vec<TraceRecord> {
+    let mut records = Vec::new();
+
+    if ok {
+        while records.len() < context {
+            records.push(record(emulator));
+            if emulator.step().is_err() {
+                break;
+            }
+        }
+    }
+
+    records
+}
+
+/// Returns the bytes changed in either memory since the snapshots which now differ,
+/// then updates the snapshots.
+fn write_differences(left: &[u8], right: &[u8], left_before: &mut [u8], right_before: &mut [u8]) -> Vec<(u16, u8, u8)> {
+    if left == left_before && right == right_before {
+        return Vec::new();
+    }
+
+    let differences = (0..left.len())
+        .filter(|&addr| (left[addr] != left_before[addr] || right[addr] != right_before[addr]) && left[addr] != right[addr])
+        .map(|addr| (addr as u16, left[addr], right[addr]))
+        .collect();
+
+    left_before.copy_from_slice(left);
+    right_before.copy_from_slice(right);
+
+    differences
+}
+
+/// Keeps the last `context` matching records.
+fn remember(before: &mut VecDeque<TraceRecord>, record: TraceRecord, context: usize) {
+    before.push_back(record);
+    if before.len() > context {
+        before.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rusty16;
+    use crate::cpu::Cpu;
+    use crate::diff::{diff, lockstep, write_differences};
+    use crate::memory::Memory;
+    use crate::surface::TestSurface;
+    use crate::audio::NullAudioSink;
+    use crate::trace::TraceRecord;
+
+    #[test]
+    fn test_diff() {
+        let (cpu, memory) = (Cpu::default(), Memory::default());
+        let left: Vec<TraceRecord> = (0..10).map(|cycle| TraceRecord::new(cycle, &cpu, &memory)).collect();
+        let mut right = left.clone();
+        right[6].r[3] = 1;
+        right[7].pc = 4;
+
+        let divergence = diff(left.iter().cloned().map(Ok), right.iter().cloned().map(Ok), 2).unwrap().unwrap();
+        assert_eq!(divergence.index, 6);
+        assert_eq!(divergence.before, left[4..6]);
+        assert_eq!(divergence.left, left[6..9]);
+        assert_eq!(divergence.right, right[6..9]);
+        assert_eq!(divergence.differences(), ["R3: 0x0000 != 0x0001"]);
+
+        let divergence = diff(left.iter().cloned().map(Ok), left[..3].iter().cloned().map(Ok), 2).unwrap().unwrap();
+        assert_eq!(divergence.index, 3);
+        assert!(divergence.right.is_empty());
+        assert_eq!(divergence.differences(), ["Right run ended"]);
+
+        assert!(diff(left.iter().cloned().map(Ok), left.iter().cloned().map(Ok), 2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let left_rom: &[u8] = &[
+            0x20, 0x00, 0x05, 0x00, // LDI R0, 5
+            0x40, 0x00, 0x01, 0x00, // ADDI R0, 1
+            0x30, 0x00, 0x00, 0x10, // STM R0, 0x1000
+            0x10, 0x00, 0x04, 0x00, // JMP 0x0004
+        ];
+        let mut right_rom = left_rom.to_vec();
+        right_rom[10] = 0x01; // STM R0, 0x1001
+
+        let mut left = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        let mut right = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        left.rom_data(left_rom).init().unwrap();
+        right.rom_data(&right_rom).init().unwrap();
+
+        assert!(lockstep(&mut left, &mut right, 2, 1).unwrap().is_none());
+
+        // The STM instructions differ.
+        let divergence = lockstep(&mut left, &mut right, 100, 1).unwrap().unwrap();
+        assert_eq!(divergence.index, 0);
+        assert_eq!(divergence.before, []);
+        assert_eq!(divergence.differences(), ["Instruction: [30, 00, 00, 10] != [30, 00, 01, 10]"]);
+        assert_eq!(divergence.left.len(), 2);
+        assert_eq!(divergence.left[1].pc, 0x0c);
+
+    }
+
+    #[test]
+    fn test_write_differences() {
+        let (mut left_before, mut right_before) = ([0, 1, 2, 3], [0, 1, 9, 3]);
+
+        // Differences which weren't written, or were written with the same value, don't count.
+        assert!(write_differences(&[0, 1, 2, 3], &[0, 1, 9, 3], &mut left_before, &mut right_before).is_empty());
+        assert!(write_differences(&[5, 1, 2, 3], &[5, 1, 9, 3], &mut left_before, &mut right_before).is_empty());
+
+        assert_eq!(write_differences(&[5, 1, 2, 4], &[5, 7, 9, 3], &mut left_before, &mut right_before),
+                   [(1, 1, 7), (3, 4, 3)]);
+        assert_eq!(left_before, [5, 1, 2, 4]);
+        assert_eq!(right_before, [5, 7, 9, 3]);
+    }
+}
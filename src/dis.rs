@@ -1,10 +1,8 @@
 extern crate rusty16;
 
 use env_logger::Env;
-use std::env;
-use rusty16::cpu::INSTRUCTION_SIZE;
-use rusty16::instruction::Instruction;
-use std::convert::TryInto;
+use std::{env, process};
+use rusty16::disasm::{disassemble, reassemble_options};
 
 fn main () {
     let log_env = Env::default()
@@ -20,10 +18,16 @@ fn main () {
     };
 
     let mut mem = rusty16::memory::Memory::default();
-    mem.load_rom(&filename);
+    if let Err(err) = mem.load_rom(&filename) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
 
-    for i in (0..mem.rom_size()).step_by(4) {
-        let instr = Instruction(mem[i as usize..i as usize + INSTRUCTION_SIZE ].try_into().expect(""));
-        println!("0x{:04X}: {}", i, instr.to_asm_str());
+    if let Some(header) = mem.rom_header() {
+        println!("; CH16 ROM, spec version {}.{}, start address 0x{:04X}",
+                 header.version >> 4, header.version & 0x0f, header.start);
+        println!("; Reassemble with: rusty16-as {} SOURCE\n", reassemble_options(header));
     }
-}
\ No newline at end of file
+
+    print!("{}", disassemble(&mem[0..mem.rom_size() as usize], mem.initial_pc()));
+}
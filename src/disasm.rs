@@ -0,0 +1,212 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use crate::asm::assemble_str;
+use crate::cpu::INSTRUCTION_SIZE;
+use crate::instruction::Instruction;
+use crate::memory::RomHeader;
+use crate::opcode::Opcode;
+
+/// Bytes per `db` line.
+const DATA_LINE_SIZE: usize = 8;
+
+/// Options which make `rusty16-as` write `header` again for the disassembled source.
+pub fn reassemble_options(header: &RomHeader) -> String {
+    let mut options = format!("--start 0x{:04X} --version {}.{}", header.start, header.version >> 4, header.version & 0x0f);
+    if header.reserved != 0 {
+        write!(options, " --reserved 0x{:02X}", header.reserved).unwrap();
+    }
+
+    options
+}
+
+/// Disassembles a ROM image into source for `asm`, which assembles back to the same bytes.
+///
+/// Code is found by following the control flow from `start`, jump and call targets
+/// get labels. Everything not reached, as well as instructions which wouldn't
+/// reassemble to the same bytes, is emitted as `db` data.
+pub fn disassemble(rom: &[u8], start: u16) -> String {
+    let code = trace(rom, start);
+    let labels = labels(rom, start, &code);
+
+    let mut source = String::new();
+    let mut addr = 0;
+
+    while addr < rom.len() {
+        if let Some(label) = labels.get(&addr) {
+            writeln!(source, "{}:", label).unwrap();
+        }
+
+        if code.contains(&addr) {
+            let instruction = Instruction(&rom[addr..addr + INSTRUCTION_SIZE]);
+            let text = match instruction.target().and_then(|target| labels.get(&(target as usize))) {
+                Some(label) => instruction.to_asm_str_with_label(label),
+                None => instruction.to_asm_str(),
+            };
+
+            writeln!(source, "    {:<32}; 0x{:04X}", text, addr).unwrap();
+            addr += INSTRUCTION_SIZE;
+            continue;
+        }
+
+        let end = (addr + 1..rom.len().min(addr + DATA_LINE_SIZE))
+            .find(|a| code.contains(a) || labels.contains_key(a))
+            .unwrap_or_else(|| rom.len().min(addr + DATA_LINE_SIZE));
+
+        let bytes: Vec<String> = rom[addr..end].iter().map(|b| format!("0x{:02X}", b)).collect();
+        writeln!(source, "    {:<32}; 0x{:04X}", format!("db {}", bytes.join(", ")), addr).unwrap();
+        addr = end;
+    }
+
+    source
+}
+
+/// Returns the addresses of all instructions reachable from `start`.
+fn trace(rom: &[u8], start: u16) -> BTreeSet<usize> {
+    let mut code = BTreeSet::new();
+    let mut pending = vec![start as usize];
+
+    while let Some(addr) = pending.pop() {
+        if addr + INSTRUCTION_SIZE > rom.len() || overlaps(&code, addr) {
+            continue;
+        }
+
+        let instruction = Instruction(&rom[addr..addr + INSTRUCTION_SIZE]);
+        if !reassembles(&instruction) {
+            continue;
+        }
+
+        code.insert(addr);
+
+        if let Some(target) = instruction.target() {
+            pending.push(target as usize);
+        }
+
+        match instruction.opcode() {
+            Some(Opcode::JMP) | Some(Opcode::JMP_R) | Some(Opcode::RET) => (),
+            _ => pending.push(addr + INSTRUCTION_SIZE),
+        }
+    }
+
+    code
+}
+
+/// Names jump and call targets which start a line of the output.
+fn labels(rom: &[u8], start: u16, code: &BTreeSet<usize>) -> BTreeMap<usize, String> {
+    let mut labels = BTreeMap::new();
+
+    for addr in code.iter() {
+        let instruction = Instruction(&rom[*addr..*addr + INSTRUCTION_SIZE]);
+        let target = match instruction.target() {
+            Some(target) => target as usize,
+            None => continue,
+        };
+
+        if target >= rom.len() || (!code.contains(&target) && overlaps(code, target)) {
+            continue;
+        }
+
+        match instruction.opcode() {
+            Some(Opcode::CALL_HHLL) | Some(Opcode::CX) => {
+                labels.insert(target, format!("sub_{:04X}", target));
+            },
+            _ => {
+                labels.entry(target).or_insert_with(|| format!("loc_{:04X}", target));
+            },
+        }
+    }
+
+    if (start as usize) < rom.len() {
+        labels.insert(start as usize, String::from("start"));
+    }
+
+    labels
+}
+
+/// Whether any instruction in `code` covers a byte of an instruction at `addr`.
+fn overlaps(code: &BTreeSet<usize>, addr: usize) -> bool {
+    code.range(addr.saturating_sub(INSTRUCTION_SIZE - 1)..addr + INSTRUCTION_SIZE).next().is_some()
+}
+
+/// Unknown opcodes, invalid conditions and set bits in unused fields don't survive a round trip.
+fn reassembles(instruction: &Instruction) -> bool {
+    assemble_str(&instruction.to_asm_str()).is_ok_and(|bytes| bytes == instruction.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::assemble_str;
+    use crate::disasm::{disassemble, reassemble_options};
+    use crate::memory::{Memory, RomHeader};
+
+    #[test]
+    fn test_disassemble() {
+        let rom: &[u8] = &[
+            0x14, 0x00, 0x10, 0x00, // CALL 0x0010
+            0x12, 0x00, 0x00, 0x00, // JZ 0x0000
+            0x10, 0x00, 0x04, 0x00, // JMP 0x0004
+            0xde, 0xad, 0xbe, 0xef, // unreachable
+            0x20, 0x01, 0x18, 0x00, // LDI R1, 0x0018
+            0x15, 0x00, 0x00, 0x00, // RET
+            0x01, 0x02,
+        ];
+
+        let source = disassemble(rom, 0);
+        let lines: Vec<&str> = source.lines().map(|line| line.split(';').next().unwrap().trim_end()).collect();
+
+        assert_eq!(lines, vec![
+            "start:",
+            "    CALL sub_0010",
+            "loc_0004:",
+            "    JZ start",
+            "    JMP loc_0004",
+            "    db 0xDE, 0xAD, 0xBE, 0xEF",
+            "sub_0010:",
+            "    LDI R1, 0x0018",
+            "    RET",
+            "    db 0x01, 0x02",
+        ]);
+        assert_eq!(assemble_str(&source).unwrap(), rom);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rom: &[u8] = &[
+            0x00, 0x00, 0x00, 0x00, // NOP
+            0x00, 0x01, 0x00, 0x00, // NOP with a set unused bit
+            0x12, 0x0f, 0x00, 0x00, // invalid condition
+            0x10, 0x00, 0x1a, 0x00, // JMP 0x001A, into data
+            0x0e, 0x42, 0xf3, 0x81, // unreachable SNG
+            0x13, 0x21, 0x16, 0x00, // unreachable JME into the middle of an instruction
+            0x00, 0x00, 0x10, 0x00,
+            0x18, 0x03, 0x00,
+        ];
+
+        for start in [0, 4, 16, 20, 26] {
+            let source = disassemble(rom, start);
+            assert_eq!(assemble_str(&source).unwrap(), rom, "start 0x{:04X}:\n{}", start, source);
+        }
+    }
+    #[test]
+    fn test_headered_round_trip() {
+        let rom: &[u8] = &[
+            0x20, 0x00, 0x05, 0x00, // LDI R0, 5
+            0x10, 0x00, 0x04, 0x00, // JMP 0x0004
+        ];
+        let header = RomHeader { version: 0x11, reserved: 0x05, ..RomHeader::new(rom, 0x0004) };
+        let mut image = header.to_bytes().to_vec();
+        image.extend_from_slice(rom);
+
+        let mut mem = Memory::default();
+        mem.load_rom_bytes(&image).unwrap();
+        let loaded = *mem.rom_header().unwrap();
+        assert_eq!(reassemble_options(&loaded), "--start 0x0004 --version 1.1 --reserved 0x05");
+
+        // What rusty16-as writes given these options.
+        let source = disassemble(&mem[0..mem.rom_size() as usize], mem.initial_pc());
+        let rom = assemble_str(&source).unwrap();
+        let header = RomHeader { version: 0x11, reserved: 0x05, ..RomHeader::new(&rom, 0x0004) };
+        let mut reassembled = header.to_bytes().to_vec();
+        reassembled.extend_from_slice(&rom);
+        assert_eq!(reassembled, image);
+    }
+}
@@ -0,0 +1,70 @@
+use std::{fmt, io};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The instruction at `pc` has an opcode the CPU doesn't know.
+    UnknownOpcode { opcode: u8, pc: u16 },
+    /// Conditional jump at `pc` with a condition code the CPU doesn't know.
+    InvalidCondition { condition: u8, pc: u16 },
+    /// Push or call at `pc` with the stack pointer at the end of the stack.
+    StackOverflow { sp: u16, pc: u16 },
+    /// Pop or return at `pc` with an empty stack.
+    StackUnderflow { sp: u16, pc: u16 },
+    /// ROM image with a missing or malformed header.
+    BadRomHeader(String),
+    /// ROM image shorter than the size declared in its header.
+    RomTruncated { expected: u32, actual: usize },
+    /// ROM image which doesn't fit into memory.
+    RomTooLarge { size: usize },
+    /// ROM image whose CRC32 doesn't match the one in its header.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// Assembler error at `line` of the source file `path`.
+    Asm { path: String, line: usize, message: String },
+    /// Execution trace which is malformed or has an unsupported version.
+    BadTrace(String),
+    /// Save state which is malformed or has an unsupported version.
+    BadState(String),
+    /// Movie which is malformed or has an unsupported version.
+    BadMovie(String),
+    /// Image path whose extension isn't a supported format.
+    UnknownImageFormat(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode { opcode, pc } => write!(f, "Unknown opcode {:#04X} at {:#06X}", opcode, pc),
+            Error::InvalidCondition { condition, pc } => write!(f, "Invalid condition code {:#X} at {:#06X}", condition, pc),
+            Error::StackOverflow { sp, pc } => write!(f, "Stack overflow at {:#06X}, SP: {:#06X}", pc, sp),
+            Error::StackUnderflow { sp, pc } => write!(f, "Stack underflow at {:#06X}, SP: {:#06X}", pc, sp),
+            Error::BadRomHeader(message) => write!(f, "Bad ROM header: {}", message),
+            Error::RomTruncated { expected, actual } => write!(f, "ROM truncated: header declares {} bytes, got {}", expected, actual),
+            Error::RomTooLarge { size } => write!(f, "ROM too large: {} bytes", size),
+            Error::ChecksumMismatch { expected, actual } => write!(f, "ROM checksum mismatch: expected {:#010X}, got {:#010X}", expected, actual),
+            Error::Asm { path, line, message } => write!(f, "{}:{}: {}", path, line, message),
+            Error::BadTrace(message) => write!(f, "Bad trace: {}", message),
+            Error::BadState(message) => write!(f, "Bad save state: {}", message),
+            Error::BadMovie(message) => write!(f, "Bad movie: {}", message),
+            Error::UnknownImageFormat(path) => write!(f, "Unknown image format: {}, expected .png or .ppm", path),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt;
 
-#[derive(Default, Debug, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq)]
 pub struct CpuFlags(pub u8);
 
 macro_rules! flag {
@@ -0,0 +1,571 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use log::{info, warn};
+use crate::{FrameTimer, Rusty16};
+use crate::audio::AudioSink;
+use crate::error::Result;
+use crate::flags::CpuFlags;
+use crate::memory::MEMORY_SIZE;
+use crate::surface::Surface;
+
+/// Registers in the order of the `g` packet: PC, SP, R0-RF and flags, 16 bits each.
+const REGISTER_COUNT: usize = 19;
+
+/// Largest packet accepted from the client.
+const PACKET_SIZE: usize = 0x4000;
+
+/// Register layout for clients which ask for a target description.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.rusty16.chip16">
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="r0" bitsize="16" type="int16"/>
+    <reg name="r1" bitsize="16" type="int16"/>
+    <reg name="r2" bitsize="16" type="int16"/>
+    <reg name="r3" bitsize="16" type="int16"/>
+    <reg name="r4" bitsize="16" type="int16"/>
+    <reg name="r5" bitsize="16" type="int16"/>
+    <reg name="r6" bitsize="16" type="int16"/>
+    <reg name="r7" bitsize="16" type="int16"/>
+    <reg name="r8" bitsize="16" type="int16"/>
+    <reg name="r9" bitsize="16" type="int16"/>
+    <reg name="ra" bitsize="16" type="int16"/>
+    <reg name="rb" bitsize="16" type="int16"/>
+    <reg name="rc" bitsize="16" type="int16"/>
+    <reg name="rd" bitsize="16" type="int16"/>
+    <reg name="re" bitsize="16" type="int16"/>
+    <reg name="rf" bitsize="16" type="int16"/>
+    <reg name="flags" bitsize="16" type="uint16"/>
+  </feature>
+</target>
+"#;
+
+/// Why the emulator stopped running.
+enum Stop {
+    Step,
+    Breakpoint,
+    Watchpoint(u16),
+    Interrupt,
+    Error,
+    Quit,
+}
+
+/// GDB remote serial protocol server, lets gdb or an IDE debug the ROM over TCP.
+///
+/// Software and hardware breakpoints are both implemented by checking the PC
+/// after every instruction. Write watchpoints compare the watched bytes after
+/// every instruction, so a write of the value already there doesn't stop.
+pub struct GdbStub<'e, 'a, S: Surface, A: AudioSink> {
+    emulator: &'e mut Rusty16<'a, S, A>,
+    breakpoints: BTreeSet<u16>,
+    /// Watched ranges by start address, with their contents when last checked.
+    watchpoints: BTreeMap<u16, Vec<u8>>,
+}
+
+impl<'e, 'a, S: Surface, A: AudioSink> GdbStub<'e, 'a, S, A> {
+    pub fn new(emulator: &'e mut Rusty16<'a, S, A>) -> Self {
+        GdbStub {
+            emulator,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Waits for a client to connect on `addr` and serves it, see `serve`.
+    pub fn listen<T: ToSocketAddrs>(&mut self, addr: T) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Waiting for GDB on {}", listener.local_addr()?);
+
+        let (stream, peer) = listener.accept()?;
+        info!("GDB connected from {}", peer);
+
+        self.serve(stream)
+    }
+
+    /// Loads the ROM and handles requests until the client detaches, kills the session or disconnects.
+    /// The emulator starts stopped at the ROM's start address.
+    pub fn serve(&mut self, stream: TcpStream) -> Result<()> {
+        self.emulator.init()?;
+
+        let mut connection = Connection::new(stream)?;
+        while let Some(packet) = connection.read_packet()? {
+            match self.handle(&packet, &mut connection)? {
+                Some(reply) => connection.send(&reply)?,
+                None => break,
+            }
+        }
+
+        info!("GDB session ended");
+        Ok(())
+    }
+
+    /// Returns the reply to `packet`, `None` once the session is over.
+    fn handle(&mut self, packet: &str, connection: &mut Connection) -> io::Result<Option<String>> {
+        let command = packet.get(..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+
+        let reply = match command {
+            "?" => stop_reply(Stop::Step),
+            "g" => encode_registers(&self.registers()),
+            "G" => self.write_registers(args).unwrap_or_else(error),
+            "p" => self.read_register(args).unwrap_or_else(error),
+            "P" => self.write_register(args).unwrap_or_else(error),
+            "m" => self.read_memory(args).unwrap_or_else(error),
+            "M" => self.write_memory(args).unwrap_or_else(error),
+            "c" | "s" => {
+                if !args.is_empty() {
+                    match parse_addr(args) {
+                        Some(addr) => self.emulator.cpu_mut().set_pc(addr),
+                        None => return Ok(Some(error())),
+                    }
+                }
+
+                match self.resume(connection, command == "s")? {
+                    Stop::Quit => {
+                        connection.send("W00")?;
+                        return Ok(None);
+                    },
+                    stop => stop_reply(stop),
+                }
+            },
+            "Z" => self.insert_point(args).unwrap_or_else(error),
+            "z" => self.remove_point(args).unwrap_or_else(error),
+            "H" => String::from("OK"),
+            "D" => {
+                connection.send("OK")?;
+                return Ok(None);
+            },
+            "k" => return Ok(None),
+            "q" => query(args),
+            // Unsupported packets get an empty reply.
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+
+    /// Runs until a breakpoint or watchpoint is hit, the client interrupts or,
+    /// for a single step, one instruction was executed.
+    fn resume(&mut self, connection: &mut Connection, single_step: bool) -> io::Result<Stop> {
+        for (addr, data) in self.watchpoints.iter_mut() {
+            *data = read(self.emulator, *addr, data.len());
+        }
+
+        let mut timer = FrameTimer::new();
+
+        loop {
+            if let Err(err) = self.emulator.step() {
+                warn!("{}", err);
+                return Ok(Stop::Error);
+            }
+
+            if self.emulator.quit_requested() {
+                return Ok(Stop::Quit);
+            }
+            if let Some(addr) = self.triggered_watchpoint() {
+                return Ok(Stop::Watchpoint(addr));
+            }
+            if single_step {
+                return Ok(Stop::Step);
+            }
+            if self.breakpoints.contains(&self.emulator.cpu().pc()) {
+                return Ok(Stop::Breakpoint);
+            }
+            if self.emulator.frame_cycles == 0 {
+                if connection.interrupted()? {
+                    return Ok(Stop::Interrupt);
+                }
+                timer.wait();
+            }
+        }
+    }
+
+    /// Returns the first watched range whose contents changed, remembering the new contents.
+    fn triggered_watchpoint(&mut self) -> Option<u16> {
+        for (addr, data) in self.watchpoints.iter_mut() {
+            let current = read(self.emulator, *addr, data.len());
+            if current != *data {
+                *data = current;
+                return Some(*addr);
+            }
+        }
+
+        None
+    }
+
+    fn registers(&self) -> [u16; REGISTER_COUNT] {
+        let cpu = self.emulator.cpu();
+        let mut registers = [0; REGISTER_COUNT];
+
+        registers[0] = cpu.pc();
+        registers[1] = cpu.sp();
+        for (i, r) in cpu.r().iter().enumerate() {
+            registers[2 + i] = *r as u16;
+        }
+        registers[REGISTER_COUNT - 1] = cpu.flags().0 as u16;
+
+        registers
+    }
+
+    fn set_register(&mut self, n: usize, val: u16) -> Option<()> {
+        let cpu = self.emulator.cpu_mut();
+
+        match n {
+            0 => cpu.set_pc(val),
+            1 => cpu.set_sp(val),
+            2..=17 => cpu.set_r(n - 2, val as i16),
+            18 => cpu.set_flags(CpuFlags(val as u8)),
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<String> {
+        let bytes = decode_hex(args)?;
+        if bytes.len() != REGISTER_COUNT * 2 {
+            return None;
+        }
+
+        for (n, val) in bytes.chunks(2).enumerate() {
+            self.set_register(n, u16::from_le_bytes([val[0], val[1]]))?;
+        }
+
+        Some(String::from("OK"))
+    }
+
+    fn read_register(&self, args: &str) -> Option<String> {
+        let n = parse_hex(args)?;
+        self.registers().get(n).map(|val| encode_hex(&val.to_le_bytes()))
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (n, val) = args.split_once('=')?;
+        let val = decode_hex(val)?;
+        if val.len() != 2 {
+            return None;
+        }
+
+        self.set_register(parse_hex(n)?, u16::from_le_bytes([val[0], val[1]]))?;
+        Some(String::from("OK"))
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = args.split_once(',')?;
+        let (addr, len) = (parse_addr(addr)?, parse_hex(len)?);
+
+        Some(encode_hex(&read(self.emulator, addr, len.min(MEMORY_SIZE - addr as usize))))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = range.split_once(',')?;
+        let (addr, len, data) = (parse_addr(addr)?, parse_hex(len)?, decode_hex(data)?);
+        if data.len() != len || addr as usize + len > MEMORY_SIZE {
+            return None;
+        }
+
+        let memory = self.emulator.memory_mut();
+        for (i, byte) in data.into_iter().enumerate() {
+            memory[addr as usize + i] = byte;
+        }
+
+        Some(String::from("OK"))
+    }
+
+    /// Handles `Z` packets, `TYPE,ADDR,KIND` where KIND is the length of watchpoints.
+    fn insert_point(&mut self, args: &str) -> Option<String> {
+        let (kind, addr, len) = parse_point(args)?;
+
+        match kind {
+            "0" | "1" => {
+                self.breakpoints.insert(addr);
+            },
+            "2" => {
+                if len == 0 || addr as usize + len > MEMORY_SIZE {
+                    return None;
+                }
+                self.watchpoints.insert(addr, read(self.emulator, addr, len));
+            },
+            // Read and access watchpoints would need every memory read to be tracked.
+            _ => return Some(String::new()),
+        }
+
+        Some(String::from("OK"))
+    }
+
+    fn remove_point(&mut self, args: &str) -> Option<String> {
+        let (kind, addr, _) = parse_point(args)?;
+
+        match kind {
+            "0" | "1" => {
+                self.breakpoints.remove(&addr);
+            },
+            "2" => {
+                self.watchpoints.remove(&addr);
+            },
+            _ => return Some(String::new()),
+        }
+
+        Some(String::from("OK"))
+    }
+}
+
+/// A client connection, which frames, checksums and acknowledges packets.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        // Packets are small and every one waits for a reply.
+        stream.set_nodelay(true)?;
+
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    /// Reads the next valid packet, acknowledging it, `None` once the client disconnected.
+    /// Acknowledgements and interrupts outside of packets are skipped.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                Some(b'$') => (),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) if data.len() < PACKET_SIZE => data.push(byte),
+                    Some(_) => (),
+                    None => return Ok(None),
+                }
+            }
+
+            let mut received = [0; 2];
+            self.reader.read_exact(&mut received)?;
+
+            let valid = std::str::from_utf8(&received).ok()
+                .and_then(|received| u8::from_str_radix(received, 16).ok()) == Some(checksum(&data));
+            if !valid {
+                self.writer.write_all(b"-")?;
+                continue;
+            }
+
+            self.writer.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        write!(self.writer, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+        self.writer.flush()
+    }
+
+    /// Whether the client sent an interrupt (Ctrl-C) or disconnected while the emulator runs.
+    /// Doesn't block.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let result = self.reader.fill_buf().map(|buffer| buffer.is_empty());
+            self.reader.get_ref().set_nonblocking(false)?;
+
+            match result {
+                Ok(true) => return Ok(true),
+                Ok(false) => (),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+
+        // While running the client only sends interrupts and acknowledgements.
+        let buffer = self.reader.buffer();
+        let interrupted = buffer.contains(&0x03);
+        let len = buffer.len();
+        self.reader.consume(len);
+
+        Ok(interrupted)
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Step | Stop::Breakpoint => String::from("S05"),
+        Stop::Watchpoint(addr) => format!("T05watch:{:x};", addr),
+        Stop::Interrupt => String::from("S02"),
+        Stop::Error => String::from("S04"),
+        Stop::Quit => String::from("W00"),
+    }
+}
+
+/// Handles `q` packets.
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        return format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+    }
+    if args == "Attached" {
+        return String::from("1");
+    }
+
+    match args.strip_prefix("Xfer:features:read:target.xml:") {
+        Some(range) => target_xml(range).unwrap_or_else(error),
+        None => String::new(),
+    }
+}
+
+/// Returns the requested `OFFSET,LENGTH` part of the target description.
+fn target_xml(range: &str) -> Option<String> {
+    let (offset, len) = range.split_once(',')?;
+    let offset = parse_hex(offset)?.min(TARGET_XML.len());
+    let end = offset.saturating_add(parse_hex(len)?).min(TARGET_XML.len());
+    let more = if end < TARGET_XML.len() { "m" } else { "l" };
+
+    Some(format!("{}{}", more, &TARGET_XML[offset..end]))
+}
+
+fn parse_point(args: &str) -> Option<(&str, u16, usize)> {
+    let mut fields = args.split(',');
+    let kind = fields.next()?;
+    let addr = parse_addr(fields.next()?)?;
+    let len = parse_hex(fields.next()?.split(';').next()?)?;
+
+    Some((kind, addr, len))
+}
+
+/// Reads `len` bytes from `addr`, wrapping around the end of memory.
+fn read<S: Surface, A: AudioSink>(emulator: &Rusty16<S, A>, addr: u16, len: usize) -> Vec<u8> {
+    let memory = emulator.memory();
+    (0..len).map(|i| memory[(addr as usize + i) & 0xffff]).collect()
+}
+
+fn error() -> String {
+    String::from("E01")
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn encode_registers(registers: &[u16]) -> String {
+    registers.iter().map(|val| encode_hex(&val.to_le_bytes())).collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() & 0x1 != 0 {
+        return None;
+    }
+
+    (0..text.len()).step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn parse_addr(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use crate::Rusty16;
+    use crate::gdb::{Connection, GdbStub};
+    use crate::surface::TestSurface;
+    use crate::audio::NullAudioSink;
+
+    const PROGRAM: &[u8] = &[
+        0x20, 0x00, 0x05, 0x00, // 0x00: LDI R0, 5
+        0x14, 0x00, 0x10, 0x00, // 0x04: CALL 0x0010
+        0x30, 0x00, 0x00, 0x10, // 0x08: STM R0, 0x1000
+        0x10, 0x00, 0x08, 0x00, // 0x0C: JMP 0x0008
+        0x40, 0x00, 0x01, 0x00, // 0x10: ADDI R0, 1
+        0x15, 0x00, 0x00, 0x00, // 0x14: RET
+    ];
+
+    fn request(connection: &mut Connection, packet: &str) -> String {
+        connection.send(packet).unwrap();
+        connection.read_packet().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut gdb = Connection::new(TcpStream::connect(addr).unwrap()).unwrap();
+
+            assert!(request(&mut gdb, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+            assert!(request(&mut gdb, "qXfer:features:read:target.xml:0,5").starts_with("m<?xm"));
+            assert_eq!(request(&mut gdb, "?"), "S05");
+
+            assert_eq!(request(&mut gdb, "s"), "S05");
+            assert_eq!(request(&mut gdb, "p0"), "0400");
+            let registers = request(&mut gdb, "g");
+            assert_eq!(registers.len(), 19 * 4);
+            assert!(registers.starts_with("0400f0fd0500"));
+
+            assert_eq!(request(&mut gdb, "Z0,14,4"), "OK");
+            assert_eq!(request(&mut gdb, "Z2,1000,2"), "OK");
+            assert_eq!(request(&mut gdb, "Z3,1000,2"), "");
+            assert_eq!(request(&mut gdb, "c"), "S05");
+            assert_eq!(request(&mut gdb, "p0"), "1400");
+            assert_eq!(request(&mut gdb, "p2"), "0600");
+            assert_eq!(request(&mut gdb, "c"), "T05watch:1000;");
+            assert_eq!(request(&mut gdb, "p0"), "0c00");
+
+            assert_eq!(request(&mut gdb, "m1000,3"), "060000");
+            assert_eq!(request(&mut gdb, "M1000,2:ffff"), "OK");
+            assert_eq!(request(&mut gdb, "m1000,2"), "ffff");
+            assert_eq!(request(&mut gdb, "mffff,2"), "00");
+            assert_eq!(request(&mut gdb, "m10000,2"), "E01");
+            assert_eq!(request(&mut gdb, "P2=2a00"), "OK");
+            assert_eq!(request(&mut gdb, "p2"), "2a00");
+            assert_eq!(request(&mut gdb, "p13"), "E01");
+
+            assert_eq!(request(&mut gdb, "z0,14,4"), "OK");
+            assert_eq!(request(&mut gdb, "z2,1000,2"), "OK");
+
+            gdb.send("c").unwrap();
+            gdb.writer.write_all(&[0x03]).unwrap();
+            assert_eq!(gdb.read_packet().unwrap().unwrap(), "S02");
+
+            assert_eq!(request(&mut gdb, "D"), "OK");
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(PROGRAM);
+        GdbStub::new(&mut emulator).serve(stream).unwrap();
+        client.join().unwrap();
+
+        assert_eq!(emulator.cpu().r()[0], 42);
+        assert_eq!(emulator.memory()[0x1000], 42);
+    }
+}
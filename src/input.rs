@@ -0,0 +1,288 @@
+use crate::memory::Memory;
+
+/// Controller I/O ports, one 16-bit word per player.
+pub const PAD_ADDR: [usize; 2] = [0xfff0, 0xfff2];
+
+/// Chip16 controller bit layout.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Button {
+    Up = 0x01,
+    Down = 0x02,
+    Left = 0x04,
+    Right = 0x08,
+    Select = 0x10,
+    Start = 0x20,
+    A = 0x40,
+    B = 0x80,
+}
+
+impl Button {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "up" => Some(Button::Up),
+            "down" => Some(Button::Down),
+            "left" => Some(Button::Left),
+            "right" => Some(Button::Right),
+            "select" => Some(Button::Select),
+            "start" => Some(Button::Start),
+            "a" => Some(Button::A),
+            "b" => Some(Button::B),
+            _ => None,
+        }
+    }
+}
+
+/// Emulator functions bound to host keys, handled between frames.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Hotkey {
+    QuickSave,
+    QuickLoad,
+    /// Plays the game backwards while held.
+    Rewind,
+    Screenshot,
+}
+
+/// Maps host key names to controller buttons. Key names are the ones
+/// reported by SDL, e.g. `Up`, `Z`, `Return` or `Left Shift`, and are
+/// compared case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct KeyMap(Vec<(String, Button)>);
+
+impl KeyMap {
+    pub fn player1() -> Self {
+        let mut map = KeyMap::default();
+        map.bind("Up", Button::Up);
+        map.bind("Down", Button::Down);
+        map.bind("Left", Button::Left);
+        map.bind("Right", Button::Right);
+        map.bind("Right Shift", Button::Select);
+        map.bind("Return", Button::Start);
+        map.bind("Z", Button::A);
+        map.bind("X", Button::B);
+        map
+    }
+
+    pub fn player2() -> Self {
+        let mut map = KeyMap::default();
+        map.bind("W", Button::Up);
+        map.bind("S", Button::Down);
+        map.bind("A", Button::Left);
+        map.bind("D", Button::Right);
+        map.bind("Tab", Button::Select);
+        map.bind("Space", Button::Start);
+        map.bind("F", Button::A);
+        map.bind("G", Button::B);
+        map
+    }
+
+    /// Parses a comma separated list of `button=key` bindings, e.g. `up=W,down=S,a=J`.
+    /// Buttons which are not mentioned keep their binding from `self`.
+    pub fn parse(mut self, spec: &str) -> Result<Self, String> {
+        for binding in spec.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+            let (button, key) = match binding.find('=') {
+                Some(i) => (&binding[..i], binding[i + 1..].trim()),
+                None => return Err(format!("Invalid key binding: {}", binding)),
+            };
+
+            let button = Button::from_name(button.trim())
+                .ok_or_else(|| format!("Unknown button: {}", button))?;
+
+            if key.is_empty() {
+                return Err(format!("Missing key for button: {:?}", button));
+            }
+
+            self.0.retain(|(_, b)| *b != button);
+            self.bind(key, button);
+        }
+
+        Ok(self)
+    }
+
+    pub fn bind(&mut self, key: &str, button: Button) {
+        self.0.push((key.to_string(), button));
+    }
+
+    fn buttons<'a>(&'a self, key: &'a str) -> impl Iterator<Item = Button> + 'a {
+        self.0.iter()
+            .filter(move |(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, button)| *button)
+    }
+}
+
+/// Host input state collected by the surface between frames.
+pub struct Input {
+    key_maps: [KeyMap; 2],
+    hotkeys: Vec<(String, Hotkey)>,
+    pads: [u8; 2],
+    /// Hotkeys pressed since the last `take_hotkeys`.
+    pressed: Vec<Hotkey>,
+    held: Vec<Hotkey>,
+    quit: bool,
+}
+
+impl Input {
+    pub fn set_key_map(&mut self, player: usize, key_map: KeyMap) {
+        self.key_maps[player] = key_map;
+        self.pads[player] = 0;
+    }
+
+    pub fn key_down(&mut self, key: &str) {
+        for player in 0..self.pads.len() {
+            for button in self.key_maps[player].buttons(key) {
+                self.pads[player] |= button as u8;
+            }
+        }
+
+        for (_, hotkey) in self.hotkeys.iter().filter(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            self.pressed.push(*hotkey);
+            if !self.held.contains(hotkey) {
+                self.held.push(*hotkey);
+            }
+        }
+    }
+
+    pub fn key_up(&mut self, key: &str) {
+        for player in 0..self.pads.len() {
+            for button in self.key_maps[player].buttons(key) {
+                self.pads[player] &= !(button as u8);
+            }
+        }
+
+        for (_, hotkey) in self.hotkeys.iter().filter(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            self.held.retain(|held| held != hotkey);
+        }
+    }
+
+    pub fn pad(&self, player: usize) -> u8 {
+        self.pads[player]
+    }
+
+    /// Returns the hotkeys pressed since the last call.
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        std::mem::take(&mut self.pressed)
+    }
+
+    /// Whether the key of `hotkey` is held down.
+    pub fn held(&self, hotkey: Hotkey) -> bool {
+        self.held.contains(&hotkey)
+    }
+
+    pub fn set_quit(&mut self) {
+        self.quit = true;
+    }
+
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    /// State of both controllers.
+    pub fn pads(&self) -> [u8; 2] {
+        self.pads
+    }
+
+    /// Writes both controllers into their I/O ports.
+    pub fn write_pads(&self, mem: &mut Memory) {
+        write_pad_ports(self.pads, mem);
+    }
+}
+
+/// Writes the state of both controllers into their I/O ports.
+pub fn write_pad_ports(pads: [u8; 2], mem: &mut Memory) {
+    for (addr, pad) in PAD_ADDR.iter().zip(pads.iter()) {
+        mem[*addr] = *pad;
+        mem[*addr + 1] = 0;
+    }
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input {
+            key_maps: [KeyMap::player1(), KeyMap::player2()],
+            hotkeys: vec![
+                (String::from("F5"), Hotkey::QuickSave),
+                (String::from("F9"), Hotkey::QuickLoad),
+                (String::from("Backspace"), Hotkey::Rewind),
+                (String::from("F12"), Hotkey::Screenshot),
+            ],
+            pads: [0; 2],
+            pressed: Vec::new(),
+            held: Vec::new(),
+            quit: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::{Input, KeyMap, Button, Hotkey};
+    use crate::memory::Memory;
+
+    #[test]
+    fn test_key_down_up() {
+        let mut input = Input::default();
+
+        input.key_down("Up");
+        input.key_down("z");
+        input.key_down("D");
+        assert_eq!(input.pad(0), Button::Up as u8 | Button::A as u8);
+        assert_eq!(input.pad(1), Button::Right as u8);
+
+        input.key_up("Up");
+        input.key_up("Unbound");
+        assert_eq!(input.pad(0), Button::A as u8);
+    }
+
+    #[test]
+    fn test_hotkeys() {
+        let mut input = Input::default();
+
+        input.key_down("F5");
+        input.key_up("F5");
+        input.key_down("f9");
+        assert_eq!(input.take_hotkeys(), [Hotkey::QuickSave, Hotkey::QuickLoad]);
+        assert!(input.take_hotkeys().is_empty());
+        assert_eq!(input.pad(0), 0);
+
+        assert!(input.held(Hotkey::QuickLoad));
+        assert!(!input.held(Hotkey::QuickSave));
+        input.key_down("Backspace");
+        assert!(input.held(Hotkey::Rewind));
+        input.key_up("Backspace");
+        assert!(!input.held(Hotkey::Rewind));
+    }
+
+    #[test]
+    fn test_parse_key_map() {
+        let map = KeyMap::player1().parse("up=W, a = J").unwrap();
+        let mut input = Input::default();
+        input.set_key_map(0, map);
+
+        input.key_down("Up");
+        assert_eq!(input.pad(0), 0);
+
+        input.key_down("W");
+        input.key_down("J");
+        input.key_down("X");
+        assert_eq!(input.pad(0), Button::Up as u8 | Button::A as u8 | Button::B as u8);
+
+        assert!(KeyMap::default().parse("up").is_err());
+        assert!(KeyMap::default().parse("jump=K").is_err());
+        assert!(KeyMap::default().parse("up=").is_err());
+    }
+
+    #[test]
+    fn test_write_pads() {
+        let mut input = Input::default();
+        let mut mem = Memory::default();
+        mem[0xfff1] = 0xff;
+
+        input.key_down("Return");
+        input.key_down("G");
+        input.write_pads(&mut mem);
+
+        assert_eq!(mem[0xfff0], Button::Start as u8);
+        assert_eq!(mem[0xfff1], 0);
+        assert_eq!(mem[0xfff2], Button::B as u8);
+        assert_eq!(mem[0xfff3], 0);
+    }
+}
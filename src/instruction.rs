@@ -36,87 +36,140 @@ impl<'a> Instruction<'a> {
         self.0[3]
     }
 
+    /// Target address of jumps and calls with an immediate operand.
+    pub fn target(&self) -> Option<u16> {
+        match self.opcode()? {
+            Opcode::JMP | Opcode::JMC | Opcode::JX | Opcode::JME | Opcode::CALL_HHLL | Opcode::CX =>
+                Some(little_endian!(self.ll(), self.hh())),
+            _ => None,
+        }
+    }
+
+    /// Formats the instruction in assembler syntax.
     pub fn to_asm_str(&self) -> String {
+        self.asm_str(None)
+    }
+
+    /// Like `to_asm_str`, but refers to the `target` of a jump or call by `label`.
+    pub fn to_asm_str_with_label(&self, label: &str) -> String {
+        self.asm_str(Some(label))
+    }
+
+    fn asm_str(&self, label: Option<&str>) -> String {
+        let (x, y, z) = (self.x(), self.y(), self.z());
+        let hhll = format!("0x{:02X}{:02X}", self.hh(), self.ll());
+        let target = label.map_or_else(|| hhll.clone(), String::from);
+
         match self.opcode() {
             Some(Opcode::NOP) => String::from("NOP"),
             Some(Opcode::CLS) => String::from("CLS"),
             Some(Opcode::VBLNK) => String::from("VBLNK"),
-            Some(Opcode::BGC) => format!("BGC {:02X}", self.z()),
-            Some(Opcode::SPR) => format!("SPR {:02X}{:02X}", self.hh(), self.ll()),
-            Some(Opcode::DRW_XY_HHLL) => format!("DRW R{:01X}, R{:01X}, {:02X}{:02X}", self.x(), self.y(), self.hh(), self.ll()),
-            Some(Opcode::DRW_XYZ) => format!("DRW R{:01X}, R{:01X}, R{:01X}", self.x(), self.y(), self.z()),
-            Some(Opcode::SND2) => format!("SND2 {:02X}{:02X}", self.hh(), self.ll()),
-            Some(Opcode::SND3) => format!("SND3 {:02X}{:02X}", self.hh(), self.ll()),
-            Some(Opcode::SNP) => format!("SNP R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::JMP) => format!("JMP {:02X}{:02X}", self.hh(), self.ll()),
-            Some(Opcode::JME) => format!("JME R{:01X}, R{:01X}, {:02X}{:02X}", self.x(), self.y(), self.hh(), self.ll()),
-            Some(Opcode::JX) => {
-                let opcode = match self.x() {
-                    0x0 => "JZ",
-                    0x1 => "JNZ",
-                    0x2 => "JN",
-                    0x3 => "JNN",
-                    0x4 => "JP",
-                    0x5 => "JO",
-                    0x6 => "JNO",
-                    0x7 => "JA",
-                    0x8 => "JAE",
-                    0x9 => "JB",
-                    0xa => "JBE",
-                    0xb => "JG",
-                    0xc => "JGE",
-                    0xd => "JL",
-                    0xe => "JLE",
-                    0xf => "RES",
-                    _ => "J??"
-                };
-
-                format!("{} {:02X}{:02X}", opcode, self.hh(), self.ll())
-            },
-            Some(Opcode::CALL_HHLL) => format!("CALL {:02X}{:02X}", self.hh(), self.ll()),
-            Some(Opcode::RET) => format!("RET"),
-            Some(Opcode::CALL) => format!("CALL R{:01X}", self.x()),
-            Some(Opcode::LDI) => format!("LDI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::LDM_HHLL) => format!("LDM SP, {:02X}{:02X}", self.hh(), self.ll()),
-            Some(Opcode::LDM_R) => format!("LDM R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::MOV) => format!("MOV R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::STM) => format!("STM R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::STM_XY) => format!("STM R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::ADDI) => format!("ADDI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::ADD_XY) => format!("ADD R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::ADD_XYZ) => format!("ADD R{:01X}, R{:01X}, R{:01X}", self.x(), self.y(), self.z()),
-            Some(Opcode::SUBI) => format!("SUBI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::SUB_XY) => format!("SUB R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::SUB_XYZ) => format!("SUB R{:01X}, R{:01X}, R{:01X}", self.x(), self.y(), self.z()),
-            Some(Opcode::CMPI) => format!("CMPI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::CMP) => format!("CMP R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::ANDI) => format!("ANDI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::AND_XY) => format!("AND R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::TSTI) => format!("TSTI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::TST) => format!("TST R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::OR_XY) => format!("OR R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::OR_XYZ) => format!("OR R{:01X}, R{:01X}, R{:01X}", self.x(), self.y(), self.z()),
-            Some(Opcode::XOR_XY) => format!("XOR R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::XOR_XYZ) => format!("XOR R{:01X}, R{:01X}, R{:01X}", self.x(), self.y(), self.z()),
-            Some(Opcode::MULI) => format!("MULI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::MUL_XY) => format!("MUL R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::MUL_XYZ) => format!("MUL R{:01X}, R{:01X}, R{:01X}", self.x(), self.y(), self.z()),
-            Some(Opcode::DIVI) => format!("DIVI R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::DIV_XY) => format!("DIV R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::SHL) => format!("SHL R{:01X}, R{:01X}", self.x(), self.z()),
-            Some(Opcode::SHR) => format!("SHR R{:01X}, {:01X}", self.x(), self.z()),
-            Some(Opcode::SAR) => format!("SAR R{:01X}, {:01X}", self.x(), self.z()),
-            Some(Opcode::SHL_XY) => format!("SHL R{:01X}, R{:01X}", self.x(), self.y()),
-            Some(Opcode::RND) => format!("RND R{:01X}, {:02X}{:02X}", self.x(), self.hh(), self.ll()),
-            Some(Opcode::PUSH) => format!("PUSH R{:01X}", self.x()),
-            Some(Opcode::POP) => format!("POP R{:01X}", self.x()),
-            Some(Opcode::PUSHF) => format!("PUSHF"),
-            Some(Opcode::PAL) => format!("PAL {:02X}{:02X}", self.hh(), self.ll()),
-            _ => String::from("??")
+            Some(Opcode::BGC) => format!("BGC 0x{:X}", z),
+            Some(Opcode::SPR) => format!("SPR {}", hhll),
+            Some(Opcode::DRW_XY_HHLL) => format!("DRW R{:X}, R{:X}, {}", x, y, hhll),
+            Some(Opcode::DRW_XYZ) => format!("DRW R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::RND) => format!("RND R{:X}, {}", x, hhll),
+            Some(Opcode::FLIP) => format!("FLIP {}, {}", (self.hh() & 0x2) >> 1, self.hh() & 0x1),
+            Some(Opcode::SND0) => String::from("SND0"),
+            Some(Opcode::SND1) => format!("SND1 {}", hhll),
+            Some(Opcode::SND2) => format!("SND2 {}", hhll),
+            Some(Opcode::SND3) => format!("SND3 {}", hhll),
+            Some(Opcode::SNP) => format!("SNP R{:X}, {}", x, hhll),
+            Some(Opcode::SNG) => format!("SNG 0x{:02X}, 0x{:02X}{:02X}", self.0[1], self.ll(), self.hh()),
+            Some(Opcode::JMP) => format!("JMP {}", target),
+            Some(Opcode::JMC) => format!("JMC {}", target),
+            Some(Opcode::JX) => format!("J{} {}", condition(x), target),
+            Some(Opcode::JME) => format!("JME R{:X}, R{:X}, {}", x, y, target),
+            Some(Opcode::CALL_HHLL) => format!("CALL {}", target),
+            Some(Opcode::RET) => String::from("RET"),
+            Some(Opcode::JMP_R) => format!("JMP R{:X}", x),
+            Some(Opcode::CX) => format!("C{} {}", condition(x), target),
+            Some(Opcode::CALL) => format!("CALL R{:X}", x),
+            Some(Opcode::LDI) => format!("LDI R{:X}, {}", x, hhll),
+            Some(Opcode::LDI_SP) => format!("LDI SP, {}", hhll),
+            Some(Opcode::LDM_HHLL) => format!("LDM R{:X}, {}", x, hhll),
+            Some(Opcode::LDM_R) => format!("LDM R{:X}, R{:X}", x, y),
+            Some(Opcode::MOV) => format!("MOV R{:X}, R{:X}", x, y),
+            Some(Opcode::STM) => format!("STM R{:X}, {}", x, hhll),
+            Some(Opcode::STM_XY) => format!("STM R{:X}, R{:X}", x, y),
+            Some(Opcode::ADDI) => format!("ADDI R{:X}, {}", x, hhll),
+            Some(Opcode::ADD_XY) => format!("ADD R{:X}, R{:X}", x, y),
+            Some(Opcode::ADD_XYZ) => format!("ADD R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::SUBI) => format!("SUBI R{:X}, {}", x, hhll),
+            Some(Opcode::SUB_XY) => format!("SUB R{:X}, R{:X}", x, y),
+            Some(Opcode::SUB_XYZ) => format!("SUB R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::CMPI) => format!("CMPI R{:X}, {}", x, hhll),
+            Some(Opcode::CMP) => format!("CMP R{:X}, R{:X}", x, y),
+            Some(Opcode::ANDI) => format!("ANDI R{:X}, {}", x, hhll),
+            Some(Opcode::AND_XY) => format!("AND R{:X}, R{:X}", x, y),
+            Some(Opcode::AND_XYZ) => format!("AND R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::TSTI) => format!("TSTI R{:X}, {}", x, hhll),
+            Some(Opcode::TST) => format!("TST R{:X}, R{:X}", x, y),
+            Some(Opcode::ORI) => format!("ORI R{:X}, {}", x, hhll),
+            Some(Opcode::OR_XY) => format!("OR R{:X}, R{:X}", x, y),
+            Some(Opcode::OR_XYZ) => format!("OR R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::XORI) => format!("XORI R{:X}, {}", x, hhll),
+            Some(Opcode::XOR_XY) => format!("XOR R{:X}, R{:X}", x, y),
+            Some(Opcode::XOR_XYZ) => format!("XOR R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::MULI) => format!("MULI R{:X}, {}", x, hhll),
+            Some(Opcode::MUL_XY) => format!("MUL R{:X}, R{:X}", x, y),
+            Some(Opcode::MUL_XYZ) => format!("MUL R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::DIVI) => format!("DIVI R{:X}, {}", x, hhll),
+            Some(Opcode::DIV_XY) => format!("DIV R{:X}, R{:X}", x, y),
+            Some(Opcode::DIV_XYZ) => format!("DIV R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::MODI) => format!("MODI R{:X}, {}", x, hhll),
+            Some(Opcode::MOD_XY) => format!("MOD R{:X}, R{:X}", x, y),
+            Some(Opcode::MOD_XYZ) => format!("MOD R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::REMI) => format!("REMI R{:X}, {}", x, hhll),
+            Some(Opcode::REM_XY) => format!("REM R{:X}, R{:X}", x, y),
+            Some(Opcode::REM_XYZ) => format!("REM R{:X}, R{:X}, R{:X}", x, y, z),
+            Some(Opcode::SHL) => format!("SHL R{:X}, {}", x, z),
+            Some(Opcode::SHR) => format!("SHR R{:X}, {}", x, z),
+            Some(Opcode::SAR) => format!("SAR R{:X}, {}", x, z),
+            Some(Opcode::SHL_XY) => format!("SHL R{:X}, R{:X}", x, y),
+            Some(Opcode::SHR_XY) => format!("SHR R{:X}, R{:X}", x, y),
+            Some(Opcode::SAR_XY) => format!("SAR R{:X}, R{:X}", x, y),
+            Some(Opcode::PUSH) => format!("PUSH R{:X}", x),
+            Some(Opcode::POP) => format!("POP R{:X}", x),
+            Some(Opcode::PUSHALL) => String::from("PUSHALL"),
+            Some(Opcode::POPALL) => String::from("POPALL"),
+            Some(Opcode::PUSHF) => String::from("PUSHF"),
+            Some(Opcode::POPF) => String::from("POPF"),
+            Some(Opcode::PAL) => format!("PAL {}", hhll),
+            Some(Opcode::PAL_R) => format!("PAL R{:X}", x),
+            Some(Opcode::NOTI) => format!("NOTI R{:X}, {}", x, hhll),
+            Some(Opcode::NOT) => format!("NOT R{:X}", x),
+            Some(Opcode::NOT_XY) => format!("NOT R{:X}, R{:X}", x, y),
+            Some(Opcode::NEGI) => format!("NEGI R{:X}, {}", x, hhll),
+            Some(Opcode::NEG) => format!("NEG R{:X}", x),
+            Some(Opcode::NEG_XY) => format!("NEG R{:X}, R{:X}", x, y),
+            None => String::from("??")
         }
     }
 }
 
+/// Condition code suffix of the Jx and Cx mnemonics.
+fn condition(x: u8) -> &'static str {
+    match x {
+        0x0 => "Z",
+        0x1 => "NZ",
+        0x2 => "N",
+        0x3 => "NN",
+        0x4 => "P",
+        0x5 => "O",
+        0x6 => "NO",
+        0x7 => "A",
+        0x8 => "AE",
+        0x9 => "B",
+        0xa => "BE",
+        0xb => "G",
+        0xc => "GE",
+        0xd => "L",
+        0xe => "LE",
+        _ => "??",
+    }
+}
+
 impl<'a> fmt::Display for Instruction<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let Some(width) = f.width() {
@@ -175,9 +228,8 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_unknown_opcode() {
-        Instruction(&[0xff, 0x01, 0x02, 0x03]).opcode();
+        assert!(Instruction(&[0xff, 0x01, 0x02, 0x03]).opcode().is_none());
     }
 
     #[test]
@@ -191,4 +243,33 @@ mod tests {
         let hh = Instruction(&[0x00, 0x11, 0x22, 0x33]).hh();
         assert_eq!(hh, 0x33);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_flip_asm_str() {
+        assert_eq!(Instruction(&[0x08, 0x00, 0x00, 0x00]).to_asm_str(), "FLIP 0, 0");
+        assert_eq!(Instruction(&[0x08, 0x00, 0x00, 0x01]).to_asm_str(), "FLIP 0, 1");
+        assert_eq!(Instruction(&[0x08, 0x00, 0x00, 0x02]).to_asm_str(), "FLIP 1, 0");
+        assert_eq!(Instruction(&[0x08, 0x00, 0x00, 0x03]).to_asm_str(), "FLIP 1, 1");
+    }
+
+    #[test]
+    fn test_condition_asm_str() {
+        assert_eq!(Instruction(&[0x12, 0x00, 0xad, 0xde]).to_asm_str(), "JZ 0xDEAD");
+        assert_eq!(Instruction(&[0x12, 0x0d, 0xad, 0xde]).to_asm_str(), "JL 0xDEAD");
+        assert_eq!(Instruction(&[0x17, 0x0b, 0x34, 0x12]).to_asm_str(), "CG 0x1234");
+    }
+
+    #[test]
+    fn test_asm_str_with_label() {
+        let call = Instruction(&[0x14, 0x00, 0x34, 0x12]);
+        assert_eq!(call.target(), Some(0x1234));
+        assert_eq!(call.to_asm_str_with_label("sub_1234"), "CALL sub_1234");
+
+        let jme = Instruction(&[0x13, 0x21, 0x34, 0x12]);
+        assert_eq!(jme.to_asm_str_with_label("loop"), "JME R1, R2, loop");
+
+        let ldm = Instruction(&[0x22, 0x03, 0x34, 0x12]);
+        assert_eq!(ldm.target(), None);
+        assert_eq!(ldm.to_asm_str(), "LDM R3, 0x1234");
+    }
+}
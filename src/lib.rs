@@ -1,38 +1,114 @@
-#![feature(array_methods)]
-
 #[macro_use]
 extern crate enum_primitive;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
-use log::info;
+use log::{info, warn};
+use crate::surface::Surface;
+#[cfg(feature = "sdl")]
 use crate::surface::SdlSurface;
-use std::time;
+use crate::audio::AudioSink;
+#[cfg(feature = "sdl")]
+use crate::audio::SdlAudioSink;
+use std::{thread, time};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use crate::input::Hotkey;
 
 #[macro_use]
 mod macros;
 
+pub mod asm;
+pub mod audio;
 pub mod cpu;
-mod flags;
+mod crc32;
+pub mod debugger;
+pub mod diff;
+pub mod disasm;
+pub mod error;
+pub mod flags;
+pub mod gdb;
+pub mod input;
 pub mod instruction;
 pub mod memory;
+pub mod movie;
 mod opcode;
-mod screen;
-mod surface;
+mod rewind;
+mod rng;
+pub mod screen;
+pub mod screenshot;
+pub mod sound;
+pub mod state;
+pub mod surface;
+pub mod trace;
+
+pub use crate::error::{Error, Result};
 
-pub struct Rusty16<'a> {
+/// Instructions executed per second. Every instruction takes one cycle.
+pub const CPU_FREQUENCY: u64 = 1_000_000;
+pub const FRAME_RATE: u64 = 60;
+
+/// The whole machine, generic over the video surface and the audio sink
+/// so it can run with SDL or headless.
+pub struct Rusty16<'a, S: Surface, A: AudioSink> {
     cpu: cpu::Cpu,
     memory: memory::Memory,
-    screen: screen::Screen<SdlSurface>,
+    screen: screen::Screen<S>,
+    sound: sound::Sound<A>,
+    input: input::Input,
+    tracer: Option<trace::Tracer>,
+    rewind: Option<rewind::Rewind>,
+    /// Movie being recorded, started by `init`.
+    recording: Option<movie::Movie>,
+    /// Movie whose input replaces the host input.
+    playback: Option<movie::Movie>,
+
+    frame: u64,
+    cycles: u64,
+    /// Cycles executed so far in the current frame.
+    frame_cycles: u64,
 
     rom_path: &'a str,
+    rom_data: Option<&'a [u8]>,
+    /// File for the quick-save and quick-load hotkeys.
+    state_path: Option<&'a str>,
+    /// Seed of the RND instruction, random if not set.
+    seed: Option<u64>,
+    /// Refuse ROMs which don't match the checksum in their header.
+    strict_checksum: bool,
+    /// Prefix of the files the screenshot hotkey writes.
+    screenshot_path: Option<&'a str>,
+    screenshot_scale: usize,
 }
 
-impl<'a> Rusty16<'a> {
+#[cfg(feature = "sdl")]
+impl<'a> Rusty16<'a, SdlSurface, SdlAudioSink> {
     pub fn new() -> Self {
+        Rusty16::with_audio_sink(SdlAudioSink::open())
+    }
+}
+
+impl<'a, S: Surface, A: AudioSink> Rusty16<'a, S, A> {
+    pub fn with_audio_sink(sink: A) -> Self {
         Rusty16 {
             cpu: cpu::Cpu::default(),
             memory: memory::Memory::default(),
-            screen: screen::Screen::<SdlSurface>::new(),
+            screen: screen::Screen::<S>::new(),
+            sound: sound::Sound::new(sink),
+            input: input::Input::default(),
+            tracer: None,
+            rewind: None,
+            recording: None,
+            playback: None,
+            frame: 0,
+            cycles: 0,
+            frame_cycles: 0,
             rom_path: "",
+            rom_data: None,
+            state_path: None,
+            seed: None,
+            strict_checksum: false,
+            screenshot_path: None,
+            screenshot_scale: 1,
         }
     }
 
@@ -41,36 +117,667 @@ impl<'a> Rusty16<'a> {
         self
     }
 
-    pub fn run(&mut self) {
-        info!("Loading ROM: {}", self.rom_path);
+    /// Loads the ROM from an in-memory image instead of `rom_path`.
+    /// Headered and raw images are both accepted.
+    pub fn rom_data(&mut self, rom_data: &'a [u8]) -> &mut Self {
+        self.rom_data = Some(rom_data);
+        self
+    }
+
+    /// Makes `init` fail with `Error::ChecksumMismatch` for ROMs which don't match
+    /// the checksum in their header instead of only logging it.
+    pub fn strict_checksum(&mut self, strict: bool) -> &mut Self {
+        self.strict_checksum = strict;
+        self
+    }
+
+    /// Replaces the keyboard mapping of controller `player` (0 or 1).
+    pub fn key_map(&mut self, player: usize, key_map: input::KeyMap) -> &mut Self {
+        self.input.set_key_map(player, key_map);
+        self
+    }
+
+    /// Sets the file the quick-save and quick-load hotkeys use.
+    pub fn state_path(&mut self, state_path: &'a str) -> &mut Self {
+        self.state_path = Some(state_path);
+        self
+    }
+
+    /// Sets where the screenshot hotkey saves PNGs, the frame number and
+    /// extension are appended to `prefix`. Images are `scale` times the screen size.
+    pub fn screenshot_path(&mut self, prefix: &'a str, scale: usize) -> &mut Self {
+        self.screenshot_path = Some(prefix);
+        self.screenshot_scale = scale;
+        self
+    }
+
+    /// Seeds the random numbers of RND so runs can be reproduced, see `Cpu::seed`
+    /// for the seed picked otherwise.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Keeps a history of the last `seconds` of emulation for `rewind_frame`,
+    /// `step_back` and the rewind hotkey. Zero disables it.
+    pub fn rewind(&mut self, seconds: u64) -> &mut Self {
+        self.rewind = match seconds {
+            0 => None,
+            _ => Some(rewind::Rewind::new((seconds * FRAME_RATE) as usize)),
+        };
+        self
+    }
+
+    /// Records the controller input of every frame from `init` on, see `movie`.
+    pub fn record_movie(&mut self) -> &mut Self {
+        self.recording = Some(movie::Movie::default());
+        self
+    }
 
-        // TODO(alexyer): Implement proper error handling.
-        self.memory.load_rom(self.rom_path).unwrap();
+    /// Replaces the host input with the frames of `movie` until it ends, and seeds
+    /// RND with the movie's seed.
+    pub fn play_movie(&mut self, movie: movie::Movie) -> &mut Self {
+        self.playback = Some(movie);
+        self
+    }
+
+    /// Records every executed instruction which passes the tracer's filters.
+    pub fn tracer(&mut self, tracer: trace::Tracer) -> &mut Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Loads the ROM and resets the machine to its start address.
+    pub fn init(&mut self) -> Result<()> {
+        match self.rom_data {
+            Some(rom_data) => {
+                info!("Loading ROM: {} bytes", rom_data.len());
+                self.memory.load_rom_bytes(rom_data)?;
+            },
+            None => {
+                info!("Loading ROM: {}", self.rom_path);
+                self.memory.load_rom(self.rom_path)?;
+            },
+        }
+        if self.strict_checksum {
+            self.memory.checksum_status()?;
+        }
 
         info!("Initializing CPU");
         self.cpu.set_pc(self.memory.initial_pc());
+        let seed = match self.playback {
+            Some(ref movie) => {
+                if movie.rom_checksum() != self.memory.rom_checksum() {
+                    warn!("Movie was recorded with another ROM, checksum {:#010X}", movie.rom_checksum());
+                }
+                movie.seed()
+            },
+            None => self.seed.unwrap_or_else(rand::random),
+        };
+        self.cpu.set_seed(seed);
+
+        if self.recording.is_some() {
+            self.recording = Some(movie::Movie::new(seed, self.memory.rom_checksum()));
+        }
 
         info!("Initializing Screen");
         self.screen.init();
 
+        info!("Initializing Sound");
+        self.sound.init();
+
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        self.init()?;
+
         info!("Starting execution");
 
+        let mut timer = FrameTimer::new();
+
         loop {
-            // TODO (alexyer):Implement proper timer.
-            let start = time::Instant::now();
-            while time::Instant::now().duration_since(start) < time::Duration::from_secs(1) {
-                let start_screen = time::Instant::now();
-                while time::Instant::now().duration_since(start_screen) < time::Duration::from_micros(16666) {
-                    self.step();
-                }
-                self.screen.poll_events();
+            self.run_frame()?;
+
+            if self.quit_requested() {
+                info!("Quit requested");
+                return Ok(());
+            }
+
+            timer.wait();
+        }
+    }
+
+    /// Emulates the rest of the current frame, see `step`.
+    pub fn run_frame(&mut self) -> Result<()> {
+        loop {
+            self.step()?;
+
+            if self.frame_cycles == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes one instruction. The first cycle of a frame polls the host input, or
+    /// takes it from the movie being played, and records the movie and the rewind
+    /// history. While the rewind hotkey is held it goes back a frame instead. Once the
+    /// frame's share of `CPU_FREQUENCY` cycles has run vblank is raised and the frame
+    /// is presented.
+    pub fn step(&mut self) -> Result<()> {
+        if self.frame_cycles == 0 {
+            self.screen.poll_events(&mut self.input);
+            self.handle_hotkeys();
+
+            if self.rewind.is_some() && self.input.held(Hotkey::Rewind) {
+                self.rewind_frame()?;
+                // Keep the audio sink fed with silence while going back.
+                self.sound.stop();
+                self.sound.update_frame();
+                return Ok(());
+            }
+
+            let pads = match self.playback.as_ref().and_then(|movie| movie.frame(self.frame)) {
+                Some(pads) => pads,
+                None => self.input.pads(),
+            };
+            input::write_pad_ports(pads, &mut self.memory);
+
+            if let Some(movie) = self.recording.as_mut() {
+                movie.set_frame(self.frame, pads);
+            }
+            self.record_frame()?;
+        }
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(self.frame, self.cycles, &self.cpu, &self.memory)?;
+        }
+
+        self.execute(true)
+    }
+
+    /// Executes one instruction without touching the host input, the rewind history or the tracer.
+    /// Finished frames are presented and their audio queued only with `output`, so replaying
+    /// rewound frames doesn't show up on screen or in the audio sink.
+    fn execute(&mut self, output: bool) -> Result<()> {
+        self.cpu.exec_instruction(&mut self.memory, &mut self.screen, &mut self.sound)?;
+        self.cycles += 1;
+        self.frame_cycles += 1;
+
+        if self.frame_cycles == cycles_in_frame(self.frame) {
+            self.frame += 1;
+            self.frame_cycles = 0;
+
+            self.screen.set_vblank();
+            if output {
                 self.screen.update_frame();
+                self.sound.update_frame();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn record_frame(&mut self) -> Result<()> {
+        if self.rewind.is_none() {
+            return Ok(());
+        }
+
+        let mut state = Vec::new();
+        self.save_state(&mut state)?;
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.push(self.cycles, state);
+        }
+
+        Ok(())
+    }
+
+    /// Goes back to the start of the current frame, or to the start of the previous
+    /// one if the current frame hasn't run yet. Returns `false` without rewind history.
+    pub fn rewind_frame(&mut self) -> Result<bool> {
+        let cycles = self.cycles;
+        let start = self.rewind.as_ref()
+            .and_then(|rewind| (0..rewind.len()).rev().map(|i| rewind.cycles(i)).find(|&start| start < cycles));
+
+        match start {
+            Some(start) => self.restore(start),
+            None => Ok(false),
+        }
+    }
+
+    /// Goes back one instruction by replaying from the last snapshot before it.
+    /// Returns `false` without rewind history.
+    pub fn step_back(&mut self) -> Result<bool> {
+        match self.cycles.checked_sub(1) {
+            Some(target) => self.restore(target),
+            None => Ok(false),
+        }
+    }
+
+    /// Steps back to the most recent instruction for which `changed` returns `true`
+    /// given the CPU before and after it ran, e.g. the one which last wrote a register.
+    /// Returns `false` and stays put if no instruction in the rewind history matches.
+    pub fn step_back_until<F>(&mut self, mut changed: F) -> Result<bool>
+        where F: FnMut(&cpu::Cpu, &cpu::Cpu) -> bool
+    {
+        // Taken out while replaying so it can be walked without copying it.
+        let history = match self.rewind.take() {
+            Some(history) => history,
+            None => return Ok(false),
+        };
+
+        let end = self.cycles;
+        let found = self.last_change(&history, end, &mut changed);
+        self.rewind = Some(history);
+
+        match found? {
+            Some(cycles) => self.restore(cycles),
+            None => {
+                self.restore(end)?;
+                Ok(false)
+            },
+        }
+    }
+
+    /// Replays the frames of `history` before `end`, newest first, and returns the cycle
+    /// count of the last instruction for which `changed` returns `true`.
+    fn last_change<F>(&mut self, history: &rewind::Rewind, end: u64, changed: &mut F) -> Result<Option<u64>>
+        where F: FnMut(&cpu::Cpu, &cpu::Cpu) -> bool
+    {
+        let mut frame_end = end;
+
+        for (start, state) in history.snapshots() {
+            if start >= frame_end {
+                continue;
+            }
+
+            self.load_state(state.as_slice())?;
+
+            let mut found = None;
+            while self.cycles < frame_end {
+                let (cycles, before) = (self.cycles, self.cpu.clone());
+                self.execute(false)?;
+
+                if changed(&before, &self.cpu) {
+                    found = Some(cycles);
+                }
+            }
+
+            if found.is_some() {
+                return Ok(found);
+            }
+
+            frame_end = start;
+        }
+
+        Ok(None)
+    }
+
+    /// Loads the newest snapshot at or before `target` cycles and replays up to it.
+    /// Newer snapshots are dropped, they're recorded again when the frames run.
+    fn restore(&mut self, target: u64) -> Result<bool> {
+        let rewind = match self.rewind.as_mut() {
+            Some(rewind) => rewind,
+            None => return Ok(false),
+        };
+
+        let len = match (0..rewind.len()).rev().find(|&i| rewind.cycles(i) <= target) {
+            Some(i) => i + 1,
+            None => return Ok(false),
+        };
+
+        rewind.truncate(len);
+        let (_, state) = rewind.snapshots().next().unwrap();
+
+        self.load_state(state.as_slice())?;
+        while self.cycles < target {
+            self.execute(false)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Writes a snapshot of the whole machine, see `state` for the format.
+    pub fn save_state<W: Write>(&self, writer: W) -> Result<()> {
+        state::save(self, writer)
+    }
+
+    /// Restores a snapshot written by `save_state`. The machine is left
+    /// untouched if the state is invalid.
+    pub fn load_state<R: Read>(&mut self, reader: R) -> Result<()> {
+        state::load(self, reader)
+    }
+
+    /// Handles the hotkeys pressed during the last frame. Failures are only
+    /// logged so they don't stop the game.
+    fn handle_hotkeys(&mut self) {
+        for hotkey in self.input.take_hotkeys() {
+            let path = match hotkey {
+                Hotkey::QuickSave | Hotkey::QuickLoad => self.state_path.map(String::from),
+                Hotkey::Screenshot => self.screenshot_path.map(|prefix| format!("{}{:06}.png", prefix, self.frame)),
+                Hotkey::Rewind => continue,
+            };
+
+            let path = match path {
+                Some(path) => path,
+                None => {
+                    warn!("{:?} ignored, no file set", hotkey);
+                    continue;
+                },
+            };
+
+            let result = match hotkey {
+                Hotkey::QuickSave => File::create(&path).map_err(Error::from).and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    self.save_state(&mut writer)?;
+                    Ok(writer.flush()?)
+                }),
+                Hotkey::QuickLoad => File::open(&path).map_err(Error::from)
+                    .and_then(|file| self.load_state(BufReader::new(file))),
+                Hotkey::Screenshot => self.save_screenshot(&path, self.screenshot_scale),
+                Hotkey::Rewind => continue,
+            };
+
+            match result {
+                Ok(()) => info!("{:?}: {}", hotkey, path),
+                Err(err) => warn!("{:?} failed: {}: {}", hotkey, path, err),
             }
         }
+    }
+
+    /// Saves the current frame as an image, PNG or PPM by the extension of `path`,
+    /// `scale` times the screen size.
+    pub fn save_screenshot(&self, path: &str, scale: usize) -> Result<()> {
+        self.screen.screenshot().save(path, scale)
+    }
+
+    /// Whether the user closed the window.
+    pub fn quit_requested(&self) -> bool {
+        self.input.quit()
+    }
+
+    /// The movie recorded so far.
+    pub fn movie(&self) -> Option<&movie::Movie> {
+        self.recording.as_ref()
+    }
+
+    /// Whether all frames of the movie being played have run.
+    pub fn movie_finished(&self) -> bool {
+        self.playback.as_ref().is_some_and(|movie| self.frame >= movie.len() as u64)
+    }
+
+    pub fn cpu(&self) -> &cpu::Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut cpu::Cpu {
+        &mut self.cpu
+    }
+
+    pub fn memory(&self) -> &memory::Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut memory::Memory {
+        &mut self.memory
+    }
+
+    pub fn screen(&self) -> &screen::Screen<S> {
+        &self.screen
+    }
 
+    /// Number of frames emulated so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
     }
 
-    pub fn step(&mut self) {
-        self.cpu.exec_instruction(&mut self.memory, &mut self.screen);
+    /// Number of cycles emulated so far.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
-}
\ No newline at end of file
+
+}
+
+/// Paces emulation to `FRAME_RATE` frames per second.
+pub(crate) struct FrameTimer {
+    deadline: time::Instant,
+}
+
+impl FrameTimer {
+    pub(crate) fn new() -> Self {
+        FrameTimer {
+            deadline: time::Instant::now(),
+        }
+    }
+
+    /// Sleeps until the current frame's time slot is over.
+    pub(crate) fn wait(&mut self) {
+        self.deadline += time::Duration::from_nanos(1_000_000_000 / FRAME_RATE);
+
+        let now = time::Instant::now();
+        if self.deadline > now {
+            thread::sleep(self.deadline - now);
+        } else {
+            // Running behind, don't try to catch up on the lost frames.
+            self.deadline = now;
+        }
+    }
+}
+
+/// Spreads `CPU_FREQUENCY` cycles over `FRAME_RATE` frames, so that every
+/// second of emulated time runs exactly `CPU_FREQUENCY` cycles.
+fn cycles_in_frame(frame: u64) -> u64 {
+    let n = frame % FRAME_RATE;
+    (n + 1) * CPU_FREQUENCY / FRAME_RATE - n * CPU_FREQUENCY / FRAME_RATE
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cycles_in_frame, CPU_FREQUENCY, FRAME_RATE, Error, Rusty16};
+    use crate::input::Button;
+    use crate::surface::TestSurface;
+    use crate::audio::{AudioSink, NullAudioSink};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_cycles_in_frame() {
+        let second: u64 = (0..FRAME_RATE).map(cycles_in_frame).sum();
+        assert_eq!(second, CPU_FREQUENCY);
+
+        for frame in 0..FRAME_RATE * 2 {
+            let cycles = cycles_in_frame(frame);
+            assert!(cycles == 16666 || cycles == 16667);
+        }
+    }
+
+    #[test]
+    fn test_headless() {
+        let program: &[u8] = &[
+            0x20, 0x00, 0x05, 0x00, // LDI R0, 5
+            0x20, 0x01, 0x03, 0x00, // LDI R1, 3
+            0x41, 0x10, 0x00, 0x00, // ADD R0, R1
+            0x30, 0x00, 0x00, 0x10, // STM R0, 0x1000
+            0x04, 0x00, 0x01, 0x01, // SPR 0x0101
+            0x05, 0x22, 0x00, 0x10, // DRW R2, R2, 0x1000
+            0x02, 0x00, 0x00, 0x00, // VBLNK
+            0x10, 0x00, 0x18, 0x00, // JMP 0x0018
+        ];
+
+        let mut rom = b"CH16\x00\x11".to_vec();
+        rom.extend_from_slice(&(program.len() as u32).to_le_bytes());
+        rom.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        rom.extend_from_slice(program);
+
+        let path = std::env::temp_dir().join("rusty16_test_headless.c16");
+        std::fs::write(&path, &rom).unwrap();
+
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_path(path.to_str().unwrap());
+        emulator.init().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        emulator.run_frame().unwrap();
+        emulator.run_frame().unwrap();
+
+        assert_eq!(emulator.frame(), 2);
+        assert_eq!(emulator.cycles(), cycles_in_frame(0) + cycles_in_frame(1));
+        assert_eq!(emulator.memory()[0x1000], 8);
+        assert_eq!(emulator.cpu().r()[0], 8);
+        assert_eq!(emulator.screen().buffer()[0][..2], [0x0, 0x8]);
+        assert_eq!(emulator.cpu().pc(), 0x18);
+    }
+
+    #[test]
+    fn test_strict_checksum() {
+        // Header with a zero checksum in front of a NOP.
+        let mut rom = b"CH16\x00\x11".to_vec();
+        rom.extend_from_slice(&4u32.to_le_bytes());
+        rom.extend_from_slice(&[0x00; 10]);
+
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(&rom).init().unwrap();
+        assert!(matches!(emulator.memory().checksum_status(), Err(Error::ChecksumMismatch { expected: 0, .. })));
+
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        match emulator.rom_data(&rom).strict_checksum(true).init() {
+            Err(Error::ChecksumMismatch { expected: 0, .. }) => (),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_raw_rom_data() {
+        let program: &[u8] = &[
+            0x20, 0x00, 0x2a, 0x00, // LDI R0, 42
+            0x10, 0x00, 0x04, 0x00, // JMP 0x0004
+        ];
+
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(program);
+        emulator.init().unwrap();
+        emulator.run_frame().unwrap();
+
+        assert!(emulator.memory().rom_header().is_none());
+        assert_eq!(emulator.cpu().r()[0], 42);
+        assert_eq!(emulator.cpu().pc(), 0x04);
+    }
+
+    #[test]
+    fn test_movie() {
+        let program: &[u8] = &[
+            0x22, 0x00, 0xf0, 0xff, // LDM R0, 0xfff0
+            0x07, 0x01, 0xff, 0x00, // RND R1, 0xff
+            0x41, 0x02, 0x00, 0x00, // ADD R2, R0
+            0x41, 0x12, 0x00, 0x00, // ADD R2, R1
+            0x30, 0x02, 0x00, 0x10, // STM R2, 0x1000
+            0x02, 0x00, 0x00, 0x00, // VBLNK
+            0x10, 0x00, 0x00, 0x00, // JMP 0x0000
+        ];
+
+        let mut recorded = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        recorded.rom_data(program).record_movie().init().unwrap();
+        for frame in 0..10 {
+            match frame {
+                3 => recorded.input.key_down("Return"),
+                6 => recorded.input.key_up("Return"),
+                _ => {},
+            }
+            recorded.run_frame().unwrap();
+        }
+
+        let movie = recorded.movie().unwrap().clone();
+        assert_eq!(movie.len(), 10);
+        assert_eq!(movie.seed(), recorded.cpu().seed());
+        assert_eq!(movie.frame(2), Some([0, 0]));
+        assert_eq!(movie.frame(5), Some([Button::Start as u8, 0]));
+
+        let mut played = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        played.rom_data(program).play_movie(movie).init().unwrap();
+        while !played.movie_finished() {
+            played.run_frame().unwrap();
+        }
+
+        assert_eq!(played.frame(), 10);
+        assert_eq!(played.cpu().r(), recorded.cpu().r());
+        assert_eq!(played.memory()[0x1000..0x1002], recorded.memory()[0x1000..0x1002]);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let program: &[u8] = &[
+            0x20, 0x00, 0x00, 0x00, // LDI R0, 0
+            0x40, 0x00, 0x01, 0x00, // ADDI R0, 1
+            0x10, 0x00, 0x04, 0x00, // JMP 0x0004
+        ];
+
+        let mut emulator = Rusty16::<TestSurface, NullAudioSink>::with_audio_sink(NullAudioSink);
+        emulator.rom_data(program).init().unwrap();
+        assert!(!emulator.step_back().unwrap());
+
+        emulator.rewind(1);
+        for _ in 0..6 {
+            emulator.step().unwrap();
+        }
+        assert_eq!((emulator.cycles(), emulator.cpu().pc(), emulator.cpu().r()[0]), (6, 0x08, 3));
+
+        assert!(emulator.step_back().unwrap());
+        assert_eq!((emulator.cycles(), emulator.cpu().pc(), emulator.cpu().r()[0]), (5, 0x04, 2));
+
+        // Back to the ADDI which set R0 to 2.
+        assert!(emulator.step_back_until(|before, after| before.r()[0] != after.r()[0]).unwrap());
+        assert_eq!((emulator.cycles(), emulator.cpu().pc(), emulator.cpu().r()[0]), (3, 0x04, 1));
+        assert!(!emulator.step_back_until(|before, after| before.r()[1] != after.r()[1]).unwrap());
+        assert_eq!(emulator.cycles(), 3);
+
+        emulator.run_frame().unwrap();
+        emulator.run_frame().unwrap();
+        emulator.step().unwrap();
+        let end = cycles_in_frame(0) + cycles_in_frame(1);
+        assert_eq!(emulator.cycles(), end + 1);
+
+        // Back over the frame boundary.
+        while emulator.cycles() > end - 2 {
+            assert!(emulator.step_back().unwrap());
+        }
+        assert_eq!(emulator.frame(), 1);
+        emulator.run_frame().unwrap();
+
+        assert!(emulator.rewind_frame().unwrap());
+        assert_eq!(emulator.cycles(), cycles_in_frame(0));
+        assert!(emulator.rewind_frame().unwrap());
+        assert_eq!(emulator.cycles(), 0);
+        assert!(!emulator.rewind_frame().unwrap());
+    }
+    /// Counts the frames of audio queued.
+    struct CountingSink(Rc<Cell<usize>>);
+
+    impl AudioSink for CountingSink {
+        fn init(&mut self) {}
+        fn queue(&mut self, _samples: &[i16]) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_rewind_replay_is_silent() {
+        let program: &[u8] = &[
+            0x40, 0x00, 0x01, 0x00, // ADDI R0, 1
+            0x10, 0x00, 0x00, 0x00, // JMP 0x0000
+        ];
+
+        let queued = Rc::new(Cell::new(0));
+        let mut emulator = Rusty16::<TestSurface, _>::with_audio_sink(CountingSink(queued.clone()));
+        emulator.rom_data(program).rewind(1).init().unwrap();
+        for _ in 0..3 {
+            emulator.run_frame().unwrap();
+        }
+        assert_eq!(queued.get(), 3);
+
+        // Both replay over frame boundaries.
+        assert!(!emulator.step_back_until(|_, _| false).unwrap());
+        assert!(emulator.step_back().unwrap());
+        assert_eq!(emulator.frame(), 2);
+        assert_eq!(queued.get(), 3);
+    }
+}
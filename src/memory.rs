@@ -1,73 +1,258 @@
-use byteorder::{ByteOrder, LittleEndian};
-use std::fs::File;
-use std::io::{Read, Error, Seek};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs;
+use std::io::{self, Read, Write};
 use std::ops::{Index, Range, IndexMut};
+use log::warn;
+use crate::cpu::INSTRUCTION_SIZE;
+use crate::crc32::crc32;
+use crate::error::{Error, Result};
+
+pub const MEMORY_SIZE: usize = 65536;
+pub const ROM_HEADER_SIZE: usize = 16;
+pub const ROM_MAGIC: [u8; 4] = *b"CH16";
+
+/// Newest specification version this emulator implements, encoded
+/// as major version in the high nibble and minor in the low one.
+pub const SPEC_VERSION: u8 = 0x13;
 
 /// Memory struct. Since chip16 maps ROM into memory this struct
 /// represents both ROM and RAM and implements ROM related functions as well.
 pub struct Memory {
-    mem: [u8; 65536],
+    mem: [u8; MEMORY_SIZE],
 
-    /// ROM file header
-    rom_header: [u8; 16],
+    /// ROM file header, `None` for raw images.
+    rom_header: Option<RomHeader>,
+    rom_size: u32,
+    /// Expected and actual CRC32 of a loaded ROM which doesn't match its header.
+    checksum_mismatch: Option<(u32, u32)>,
 }
 
-#[derive(Debug)]
-pub struct MemoryError {
-    kind: String,
-    message: String,
+/// Header of a CH16 ROM image.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RomHeader {
+    pub magic: [u8; 4],
+    /// Reserved byte between the magic and the version, normally zero.
+    pub reserved: u8,
+    /// Specification version, major version in the high nibble, minor in the low one.
+    pub version: u8,
+    /// Size of the ROM image without the header.
+    pub size: u32,
+    pub start: u16,
+    /// CRC32 of the ROM image without the header.
+    pub checksum: u32,
 }
 
-impl Memory {
-    // TODO(alexyer): Implement ROM size and checksum checks.
-    pub fn load_rom(&mut self, rom_path: &str) -> Result<(), MemoryError> {
-        let mut rom = File::open(rom_path)?;
-        self.read_rom_header(&mut rom)?;
+impl RomHeader {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < ROM_HEADER_SIZE {
+            return Err(Error::BadRomHeader(format!("Header too short: {} bytes", data.len())));
+        }
 
-        rom.seek(std::io::SeekFrom::Start(16))?;
-        rom.read(self.mem.as_mut_slice())?;
+        if data[..4] != ROM_MAGIC {
+            return Err(Error::BadRomHeader(String::from("Can't recognize ROM format")));
+        }
+
+        Ok(RomHeader {
+            magic: ROM_MAGIC,
+            reserved: data[4],
+            version: data[5],
+            size: LittleEndian::read_u32(&data[6..10]),
+            start: LittleEndian::read_u16(&data[10..12]),
+            checksum: LittleEndian::read_u32(&data[12..16]),
+        })
+    }
+
+    /// Builds the header of a ROM image with the current specification version.
+    pub fn new(rom: &[u8], start: u16) -> Self {
+        RomHeader {
+            magic: ROM_MAGIC,
+            reserved: 0,
+            version: SPEC_VERSION,
+            size: rom.len() as u32,
+            start,
+            checksum: crc32(rom),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; ROM_HEADER_SIZE] {
+        let mut data = [0; ROM_HEADER_SIZE];
+        data[..4].copy_from_slice(&self.magic);
+        data[4] = self.reserved;
+        data[5] = self.version;
+        LittleEndian::write_u32(&mut data[6..10], self.size);
+        LittleEndian::write_u16(&mut data[10..12], self.start);
+        LittleEndian::write_u32(&mut data[12..16], self.checksum);
+        data
+    }
+
+    /// Checks the specification version and that `rom`, the image without
+    /// its header, matches the declared size and fits into memory.
+    pub fn validate(&self, rom: &[u8]) -> Result<()> {
+        if self.version >> 4 > SPEC_VERSION >> 4 {
+            return Err(Error::BadRomHeader(
+                format!("Unsupported spec version {}.{}", self.version >> 4, self.version & 0x0f)));
+        }
+
+        if self.size as usize > MEMORY_SIZE {
+            return Err(Error::RomTooLarge { size: self.size as usize });
+        }
+
+        if rom.len() < self.size as usize {
+            return Err(Error::RomTruncated { expected: self.size, actual: rom.len() });
+        }
+
+        Ok(())
+    }
+
+    pub fn verify_checksum(&self, rom: &[u8]) -> Result<()> {
+        let actual = crc32(&rom[..(self.size as usize).min(rom.len())]);
+        if actual != self.checksum {
+            return Err(Error::ChecksumMismatch { expected: self.checksum, actual });
+        }
 
         Ok(())
     }
+}
+
+impl Memory {
+    /// Loads a ROM image from a file, see `load_rom_bytes`.
+    pub fn load_rom(&mut self, rom_path: &str) -> Result<()> {
+        self.load_rom_from(fs::File::open(rom_path)?)
+    }
+
+    /// Loads a ROM image from any reader, see `load_rom_bytes`.
+    pub fn load_rom_from<R: Read>(&mut self, mut reader: R) -> Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        self.load_rom_bytes(&data)
+    }
 
-    fn read_rom_header(&mut self, rom: &mut File) -> Result<(), MemoryError> {
-        rom.seek(std::io::SeekFrom::Start(0))?;
-        rom.read(self.rom_header.as_mut_slice())?;
+    /// Loads a ROM image into memory at address 0. Images starting with the
+    /// `CH16` magic are headered: a bad header or size is rejected and checksum
+    /// mismatches are logged and reported by `checksum_status`. Anything else is
+    /// a raw image starting at PC 0.
+    pub fn load_rom_bytes(&mut self, data: &[u8]) -> Result<()> {
+        let mut checksum_mismatch = None;
+        let (header, rom) = if data.starts_with(&ROM_MAGIC) {
+            let header = RomHeader::parse(data)?;
+            let rom = &data[ROM_HEADER_SIZE..];
 
-        match &self.rom_header[..4] {
-            [b'C', b'H', b'1', b'6'] => Ok(()),
-            _ => return Err(
-                MemoryError {
-                    kind: String::from("RomNotRecognized"),
-                    message: String::from("Can't recognize ROM format"),
+            header.validate(rom)?;
+            if let Err(err) = header.verify_checksum(rom) {
+                warn!("{}", err);
+                if let Error::ChecksumMismatch { expected, actual } = err {
+                    checksum_mismatch = Some((expected, actual));
                 }
-            ),
+            }
+
+            if rom.len() > header.size as usize {
+                warn!("Ignoring {} bytes past the declared ROM size", rom.len() - header.size as usize);
+            }
+
+            (Some(header), &rom[..header.size as usize])
+        } else {
+            if data.len() > MEMORY_SIZE {
+                return Err(Error::RomTooLarge { size: data.len() });
+            }
+
+            (None, data)
+        };
+
+        self.mem[..rom.len()].copy_from_slice(rom);
+        self.rom_header = header;
+        self.rom_size = rom.len() as u32;
+        self.checksum_mismatch = checksum_mismatch;
+
+        Ok(())
+    }
+
+    /// `Error::ChecksumMismatch` if the last loaded ROM doesn't match the checksum
+    /// in its header. Raw images have no checksum and always pass.
+    pub fn checksum_status(&self) -> Result<()> {
+        match self.checksum_mismatch {
+            Some((expected, actual)) => Err(Error::ChecksumMismatch { expected, actual }),
+            None => Ok(()),
         }
     }
 
+    /// Header of the loaded ROM, `None` for raw images.
+    pub fn rom_header(&self) -> Option<&RomHeader> {
+        self.rom_header.as_ref()
+    }
+
     pub fn rom_size(&self) -> u32 {
-        LittleEndian::read_u32(&self.rom_header[6..=10])
+        self.rom_size
+    }
+
+    /// CRC32 of the loaded ROM image without its header.
+    pub fn rom_checksum(&self) -> u32 {
+        crc32(&self.mem[..self.rom_size as usize])
+    }
+
+    /// Bytes of the instruction at `addr`, wrapping around at the end of memory.
+    pub fn instruction_bytes(&self, addr: u16) -> [u8; INSTRUCTION_SIZE] {
+        let mut bytes = [0; INSTRUCTION_SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.mem[(addr as usize + i) & 0xffff];
+        }
+
+        bytes
     }
 
     pub fn initial_pc(&self) -> u16 {
-        LittleEndian::read_u16(&self.rom_header[10..13])
+        self.rom_header.map_or(0, |header| header.start)
     }
-}
 
-impl Default for Memory {
-    fn default() -> Self {
-        Memory {
-            mem: [0; 65536],
-            rom_header: [0; 16],
+    /// Writes the ROM header, the ROM size and all of memory for a save state.
+    pub(crate) fn save_state<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self.rom_header {
+            Some(header) => {
+                writer.write_u8(1)?;
+                writer.write_all(&header.to_bytes())?;
+            },
+            None => {
+                writer.write_u8(0)?;
+                writer.write_all(&[0; ROM_HEADER_SIZE])?;
+            },
         }
+
+        writer.write_u32::<LittleEndian>(self.rom_size)?;
+        writer.write_all(&self.mem)
+    }
+
+    pub(crate) fn load_state<R: Read>(&mut self, reader: &mut R) -> Result<()> {
+        let has_header = reader.read_u8()?;
+        let mut header = [0; ROM_HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+
+        self.rom_header = match has_header {
+            0 => None,
+            1 => Some(RomHeader::parse(&header).map_err(|err| Error::BadState(err.to_string()))?),
+            _ => return Err(Error::BadState(format!("Invalid ROM header flag {}", has_header))),
+        };
+
+        self.rom_size = reader.read_u32::<LittleEndian>()?;
+        if self.rom_size as usize > MEMORY_SIZE {
+            return Err(Error::BadState(format!("ROM size {} doesn't fit into memory", self.rom_size)));
+        }
+        if let Some(header) = self.rom_header {
+            if header.size != self.rom_size {
+                return Err(Error::BadState(format!("ROM size {} doesn't match its header's {}", self.rom_size, header.size)));
+            }
+        }
+        reader.read_exact(&mut self.mem)?;
+
+        Ok(())
     }
 }
 
-impl From<std::io::Error> for MemoryError {
-    fn from(err: Error) -> Self {
-        MemoryError {
-            kind: String::from("RomNotFound"),
-            message: err.to_string(),
+impl Default for Memory {
+    fn default() -> Self {
+        Memory {
+            mem: [0; MEMORY_SIZE],
+            rom_header: None,
+            rom_size: 0,
+            checksum_mismatch: None,
         }
     }
 }
@@ -92,4 +277,135 @@ impl IndexMut<usize> for Memory {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         &mut self.mem[index]
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{Memory, RomHeader, MEMORY_SIZE, ROM_HEADER_SIZE};
+    use crate::error::Error;
+
+    fn rom_image(rom: &[u8]) -> Vec<u8> {
+        let mut image = RomHeader::new(rom, 0x0004).to_bytes().to_vec();
+        image.extend_from_slice(rom);
+        image
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let image = rom_image(&[0x01, 0x02, 0x03, 0x04, 0x05]);
+        let header = RomHeader::parse(&image).unwrap();
+
+        assert_eq!(&header.magic, b"CH16");
+        assert_eq!(header.version, 0x13);
+        assert_eq!(header.size, 5);
+        assert_eq!(header.start, 0x0004);
+        assert_eq!(header.checksum, 0x470b99f4);
+        assert_eq!(header.to_bytes()[..], image[..ROM_HEADER_SIZE]);
+
+        assert!(matches!(RomHeader::parse(&image[..15]), Err(Error::BadRomHeader(_))));
+        assert!(matches!(RomHeader::parse(b"CH17\0\0\0\0\0\0\0\0\0\0\0\0"), Err(Error::BadRomHeader(_))));
+    }
+
+    #[test]
+    fn test_validate() {
+        let rom = [0xaa; 8];
+        let mut header = RomHeader::new(&rom, 0);
+
+        assert!(header.validate(&rom).is_ok());
+        assert!(header.verify_checksum(&rom).is_ok());
+        assert!(matches!(header.validate(&rom[..7]), Err(Error::RomTruncated { expected: 8, actual: 7 })));
+
+        header.checksum ^= 1;
+        assert!(matches!(header.verify_checksum(&rom), Err(Error::ChecksumMismatch { .. })));
+
+        header.size = 65537;
+        assert!(matches!(header.validate(&rom), Err(Error::RomTooLarge { size: 65537 })));
+
+        header.size = 8;
+        header.version = 0x20;
+        assert!(matches!(header.validate(&rom), Err(Error::BadRomHeader(_))));
+    }
+
+    #[test]
+    fn test_load_rom() {
+        let path = std::env::temp_dir().join("rusty16_test_load_rom.c16");
+        let path = path.to_str().unwrap();
+
+        let mut image = rom_image(&[0xde, 0xad, 0xbe, 0xef]);
+        image.push(0xff);
+        std::fs::write(path, &image).unwrap();
+
+        let mut mem = Memory::default();
+        mem.load_rom(path).unwrap();
+
+        assert_eq!(mem[0..5], [0xde, 0xad, 0xbe, 0xef, 0x00]);
+        assert_eq!(mem.rom_size(), 4);
+        assert_eq!(mem.initial_pc(), 0x0004);
+
+        std::fs::write(path, &image[..ROM_HEADER_SIZE + 3]).unwrap();
+        assert!(matches!(mem.load_rom(path), Err(Error::RomTruncated { .. })));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_rom_bytes() {
+        let mut mem = Memory::default();
+        mem.load_rom_from(&rom_image(&[0x01, 0x02, 0x03])[..]).unwrap();
+
+        assert_eq!(mem[0..3], [0x01, 0x02, 0x03]);
+        assert_eq!(mem.rom_header().unwrap().size, 3);
+        assert_eq!(mem.initial_pc(), 0x0004);
+
+        // Anything without the magic is a raw image.
+        mem.load_rom_bytes(&[0x10, 0x00, 0x00, 0x00, 0xaa]).unwrap();
+
+        assert_eq!(mem[0..5], [0x10, 0x00, 0x00, 0x00, 0xaa]);
+        assert!(mem.rom_header().is_none());
+        assert_eq!(mem.rom_size(), 5);
+        assert_eq!(mem.initial_pc(), 0);
+
+        assert!(matches!(mem.load_rom_bytes(&vec![0; 65537]), Err(Error::RomTooLarge { size: 65537 })));
+        assert!(mem.load_rom_bytes(&vec![0; 65536]).is_ok());
+    }
+
+    #[test]
+    fn test_load_bad_state() {
+        let state = |has_header: u8, header: [u8; ROM_HEADER_SIZE], rom_size: u32| {
+            let mut state = vec![has_header];
+            state.extend_from_slice(&header);
+            state.extend_from_slice(&rom_size.to_le_bytes());
+            state.extend_from_slice(&[0; MEMORY_SIZE]);
+            state
+        };
+        let header = RomHeader::new(&[0; 4], 0).to_bytes();
+
+        let mut mem = Memory::default();
+        mem.load_state(&mut &state(1, header, 4)[..]).unwrap();
+        assert_eq!(mem.rom_size(), 4);
+
+        for bad in [state(0, [0; ROM_HEADER_SIZE], 0x10001), state(1, header, 8), state(2, header, 4), state(1, [0; ROM_HEADER_SIZE], 0)] {
+            let mut mem = Memory::default();
+            assert!(matches!(mem.load_state(&mut &bad[..]), Err(Error::BadState(_))));
+        }
+    }
+
+    #[test]
+    fn test_checksum_status() {
+        let mut mem = Memory::default();
+        let mut image = rom_image(&[0x01, 0x02, 0x03]);
+        mem.load_rom_bytes(&image).unwrap();
+        assert!(mem.checksum_status().is_ok());
+
+        // Corrupt ROMs still load, the mismatch is reported separately.
+        image[ROM_HEADER_SIZE] = 0xff;
+        mem.load_rom_bytes(&image).unwrap();
+        match mem.checksum_status() {
+            Err(Error::ChecksumMismatch { expected, actual }) => assert_ne!(expected, actual),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        mem.load_rom_bytes(&[0x01, 0x02, 0x03]).unwrap();
+        assert!(mem.checksum_status().is_ok());
+    }
+}
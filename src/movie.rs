@@ -0,0 +1,154 @@
+//! Movies, the controller input of every frame together with the seed of the RND
+//! instruction, so that playing one back reproduces the recorded session exactly.
+//!
+//! A movie starts with `MOVIE_MAGIC` and the `MOVIE_VERSION` byte, followed by the seed
+//! (u64), the CRC32 of the ROM it was recorded with (u32) and two bytes per frame with
+//! the state of controller 1 and 2 as written to their I/O ports. Numbers are little-endian.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::{Error, Result};
+
+pub const MOVIE_MAGIC: [u8; 4] = *b"R16M";
+pub const MOVIE_VERSION: u8 = 1;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Movie {
+    seed: u64,
+    rom_checksum: u32,
+    frames: Vec<[u8; 2]>,
+}
+
+impl Movie {
+    pub fn new(seed: u64, rom_checksum: u32) -> Self {
+        Movie {
+            seed,
+            rom_checksum,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let truncated = |_| Error::BadMovie(String::from("Truncated header"));
+
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+        if magic != MOVIE_MAGIC {
+            return Err(Error::BadMovie(String::from("Not a movie")));
+        }
+
+        let version = reader.read_u8().map_err(truncated)?;
+        if version != MOVIE_VERSION {
+            return Err(Error::BadMovie(format!("Unsupported version {}, expected {}", version, MOVIE_VERSION)));
+        }
+
+        let mut movie = Movie::new(reader.read_u64::<LittleEndian>().map_err(truncated)?,
+                                   reader.read_u32::<LittleEndian>().map_err(truncated)?);
+
+        let mut frames = Vec::new();
+        reader.read_to_end(&mut frames)?;
+        if frames.len() % 2 != 0 {
+            return Err(Error::BadMovie(String::from("Truncated frame")));
+        }
+
+        movie.frames = frames.chunks_exact(2).map(|pads| [pads[0], pads[1]]).collect();
+        Ok(movie)
+    }
+
+    pub fn open(path: &str) -> Result<Self> {
+        Movie::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(&MOVIE_MAGIC)?;
+        writer.write_u8(MOVIE_VERSION)?;
+        writer.write_u64::<LittleEndian>(self.seed)?;
+        writer.write_u32::<LittleEndian>(self.rom_checksum)?;
+
+        for pads in self.frames.iter() {
+            writer.write_all(pads)?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    /// Seed of the RND instruction the movie was recorded with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// CRC32 of the ROM the movie was recorded with, see `Memory::rom_checksum`.
+    pub fn rom_checksum(&self) -> u32 {
+        self.rom_checksum
+    }
+
+    /// Number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Controller state of `frame`, `None` past the end of the movie.
+    pub fn frame(&self, frame: u64) -> Option<[u8; 2]> {
+        self.frames.get(frame as usize).copied()
+    }
+
+    /// Records the controller state of `frame`. Later frames are dropped, so going back
+    /// by rewinding or loading a state records over them. Skipped frames are left idle.
+    pub fn set_frame(&mut self, frame: u64, pads: [u8; 2]) {
+        self.frames.resize(frame as usize, [0; 2]);
+        self.frames.push(pads);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::movie::Movie;
+
+    #[test]
+    fn test_read_write() {
+        let mut movie = Movie::new(0x1234, 0xdeadbeef);
+        for frame in 0..4 {
+            movie.set_frame(frame, [frame as u8, 0x80]);
+        }
+
+        let mut data = Vec::new();
+        movie.write(&mut data).unwrap();
+        assert_eq!(&data[..5], b"R16M\x01");
+        assert_eq!(data.len(), 5 + 8 + 4 + 4 * 2);
+        assert_eq!(Movie::read(data.as_slice()).unwrap(), movie);
+
+        assert!(matches!(Movie::read(&data[..data.len() - 1]), Err(Error::BadMovie(_))));
+        assert!(matches!(Movie::read(&data[..10]), Err(Error::BadMovie(_))));
+        data[4] = 2;
+        assert!(matches!(Movie::read(data.as_slice()), Err(Error::BadMovie(_))));
+    }
+
+    #[test]
+    fn test_set_frame() {
+        let mut movie = Movie::default();
+        movie.set_frame(0, [1, 0]);
+        movie.set_frame(1, [2, 0]);
+        movie.set_frame(2, [3, 0]);
+
+        // Going back records over the later frames.
+        movie.set_frame(1, [4, 4]);
+        assert_eq!(movie.len(), 2);
+        assert_eq!(movie.frame(1), Some([4, 4]));
+        assert_eq!(movie.frame(2), None);
+
+        movie.set_frame(4, [5, 0]);
+        assert_eq!(movie.len(), 5);
+        assert_eq!(movie.frame(3), Some([0, 0]));
+    }
+}
@@ -11,6 +11,9 @@ enum_from_primitive! {
         DRW_XY_HHLL = 0x05,
         DRW_XYZ = 0x06,
         RND = 0x07,
+        FLIP = 0x08,
+        SND0 = 0x09,
+        SND1 = 0x0a,
         SND2 = 0x0b,
         SND3 = 0x0c,
         SNP = 0x0d,
@@ -18,14 +21,18 @@ enum_from_primitive! {
 
         // 1x - Jumps
         JMP = 0x10,
+        JMC = 0x11,
         JX = 0x12,
         JME = 0x13,
         CALL_HHLL = 0x14,
         RET = 0x15,
+        JMP_R = 0x16,
+        CX = 0x17,
         CALL = 0x18,
 
         // 2x - Loads
         LDI = 0x20,
+        LDI_SP = 0x21,
         LDM_HHLL = 0x22,
         LDM_R = 0x23,
         MOV = 0x24,
@@ -54,10 +61,12 @@ enum_from_primitive! {
         TST = 0x64,
 
         // 7x - Bitwise OR
+        ORI = 0x70,
         OR_XY = 0x71,
         OR_XYZ = 0x72,
 
         // 8x - Bitwise XOR (^)
+        XORI = 0x80,
         XOR_XY = 0x81,
         XOR_XYZ = 0x82,
 
@@ -66,23 +75,44 @@ enum_from_primitive! {
         MUL_XY = 0x91,
         MUL_XYZ = 0x92,
 
-        // Ax - Division
+        // Ax - Division, modulus, remainder
         DIVI = 0xa0,
         DIV_XY = 0xa1,
+        DIV_XYZ = 0xa2,
+        MODI = 0xa3,
+        MOD_XY = 0xa4,
+        MOD_XYZ = 0xa5,
+        REMI = 0xa6,
+        REM_XY = 0xa7,
+        REM_XYZ = 0xa8,
 
         // Bx - Logical/Arithmetic Shifts
         SHL = 0xb0,
         SHR = 0xb1,
         SAR = 0xb2,
         SHL_XY = 0xb3,
+        SHR_XY = 0xb4,
+        SAR_XY = 0xb5,
 
         // Cx - Push/Pop
         PUSH = 0xc0,
         POP = 0xc1,
+        PUSHALL = 0xc2,
+        POPALL = 0xc3,
         PUSHF = 0xc4,
+        POPF = 0xc5,
 
         // Dx - Palette
         PAL = 0xd0,
+        PAL_R = 0xd1,
+
+        // Ex - Not/Neg
+        NOTI = 0xe0,
+        NOT = 0xe1,
+        NOT_XY = 0xe2,
+        NEGI = 0xe3,
+        NEG = 0xe4,
+        NEG_XY = 0xe5,
     }
 }
 
@@ -104,11 +134,15 @@ enum_from_primitive! {
         N = 0x2,
         NN = 0x3,
         P = 0x4,
+        O = 0x5,
+        NO = 0x6,
         A = 0x7,
         AE = 0x8,
         B = 0x9,
         BE = 0xa,
+        G = 0xb,
         GE = 0xc,
+        L = 0xd,
         LE = 0xe,
     }
 }
@@ -0,0 +1,200 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+/// Unchanged bytes which end a run of changed ones in a delta.
+const MIN_GAP: usize = 8;
+
+/// History of save states taken at the start of each frame. Only the newest one
+/// is kept whole, older ones are stored as compressed deltas to the next newer one.
+pub(crate) struct Rewind {
+    /// Maximum number of snapshots.
+    capacity: usize,
+    newest: Vec<u8>,
+    /// Cycle counts of the snapshots, oldest first.
+    cycles: VecDeque<u64>,
+    /// `deltas[i]` turns snapshot `i + 1` into snapshot `i`.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity: capacity.max(1),
+            newest: Vec::new(),
+            cycles: VecDeque::new(),
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.cycles.len()
+    }
+
+    pub(crate) fn cycles(&self, i: usize) -> u64 {
+        self.cycles[i]
+    }
+
+    /// Adds the state at `cycles`, which replaces the newest snapshot if that was taken at the same cycle.
+    pub(crate) fn push(&mut self, cycles: u64, state: Vec<u8>) {
+        if self.cycles.back() == Some(&cycles) {
+            if let Some(older) = self.deltas.pop_back().map(|delta| apply(&self.newest, &delta)) {
+                self.deltas.push_back(delta(&state, &older));
+            }
+
+            self.newest = state;
+            return;
+        }
+
+        if !self.cycles.is_empty() {
+            self.deltas.push_back(delta(&state, &self.newest));
+        }
+
+        self.newest = state;
+        self.cycles.push_back(cycles);
+
+        if self.cycles.len() > self.capacity {
+            self.cycles.pop_front();
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Drops all snapshots newer than the first `len`.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        while self.cycles.len() > len.max(1) {
+            let delta = self.deltas.pop_back().unwrap();
+            self.newest = apply(&self.newest, &delta);
+            self.cycles.pop_back();
+        }
+
+        if len == 0 {
+            self.newest.clear();
+            self.cycles.clear();
+        }
+    }
+
+    /// Snapshots with their cycle counts, newest first.
+    pub(crate) fn snapshots(&self) -> impl Iterator<Item = (u64, Vec<u8>)> + '_ {
+        let mut state = self.newest.clone();
+        let mut i = self.cycles.len();
+
+        std::iter::from_fn(move || {
+            if i == 0 {
+                return None;
+            }
+
+            i -= 1;
+            if i + 1 < self.cycles.len() {
+                state = apply(&state, &self.deltas[i]);
+            }
+
+            Some((self.cycles[i], state.clone()))
+        })
+    }
+}
+
+/// Encodes the bytes of `newer` which differ from `older` as runs of
+/// unchanged length (u32), changed length (u32) and the changed bytes XORed.
+fn delta(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    debug_assert_eq!(newer.len(), older.len());
+
+    let mut delta = Vec::new();
+    let (mut i, mut last) = (0, 0);
+
+    while i < newer.len() {
+        if newer[i] == older[i] {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut end = i + 1;
+        i += 1;
+        while i < newer.len() && i - end < MIN_GAP {
+            if newer[i] != older[i] {
+                end = i + 1;
+            }
+            i += 1;
+        }
+
+        delta.extend_from_slice(&((start - last) as u32).to_le_bytes());
+        delta.extend_from_slice(&((end - start) as u32).to_le_bytes());
+        delta.extend(newer[start..end].iter().zip(&older[start..end]).map(|(n, o)| n ^ o));
+
+        last = end;
+        i = end;
+    }
+
+    delta
+}
+
+/// Applies a delta from `delta`, which works in both directions.
+fn apply(state: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut state = state.to_vec();
+    let (mut pos, mut offset) = (0, 0);
+
+    while offset < delta.len() {
+        let skip = u32::from_le_bytes(delta[offset..offset + 4].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(delta[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        offset += 8;
+        pos += skip;
+
+        for (byte, xor) in state[pos..pos + len].iter_mut().zip(&delta[offset..offset + len]) {
+            *byte ^= xor;
+        }
+
+        pos += len;
+        offset += len;
+    }
+
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rewind::{apply, delta, Rewind};
+
+    #[test]
+    fn test_delta() {
+        let older = vec![0u8; 100];
+        let mut newer = older.clone();
+        newer[3] = 1;
+        newer[5] = 2;
+        newer[50] = 3;
+        newer[99] = 4;
+
+        let delta = delta(&newer, &older);
+        // Bytes 3 to 5 in one run, 50 and 99 in their own.
+        assert_eq!(delta.len(), 3 * 8 + 3 + 1 + 1);
+        assert_eq!(apply(&newer, &delta), older);
+        assert_eq!(apply(&older, &delta), newer);
+
+        assert!(super::delta(&older, &older).is_empty());
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut rewind = Rewind::new(3);
+        for cycles in 0..5 {
+            rewind.push(cycles * 10, vec![cycles as u8; 16]);
+        }
+
+        // Replaces the newest snapshot.
+        rewind.push(40, vec![9; 16]);
+
+        assert_eq!(rewind.len(), 3);
+        let snapshots: Vec<(u64, Vec<u8>)> = rewind.snapshots().collect();
+        assert_eq!(snapshots, [(40, vec![9; 16]), (30, vec![3; 16]), (20, vec![2; 16])]);
+
+        rewind.truncate(2);
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.cycles(1), 30);
+        assert_eq!(rewind.snapshots().next(), Some((30, vec![3; 16])));
+
+        rewind.push(40, vec![5; 16]);
+        assert_eq!(rewind.snapshots().nth(1), Some((30, vec![3; 16])));
+
+        rewind.truncate(0);
+        assert_eq!(rewind.len(), 0);
+        assert_eq!(rewind.snapshots().next(), None);
+    }
+}
@@ -0,0 +1,42 @@
+/// SplitMix64, a small PRNG whose whole state is one number, so runs with the
+/// same seed draw the same numbers and the state fits in a save state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Draws a number from `0` to `max`, inclusive.
+    pub(crate) fn gen_range(&mut self, max: u16) -> u16 {
+        (((self.next_u64() >> 32) * (max as u64 + 1)) >> 32) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rng::Rng;
+
+    #[test]
+    fn test_rng() {
+        // Reference values of SplitMix64 seeded with 0.
+        let mut rng = Rng(0);
+        assert_eq!(rng.next_u64(), 0xe220a8397b1dcdaf);
+        assert_eq!(rng.next_u64(), 0x6e789e6aa1b965f4);
+
+        let (mut a, mut b) = (Rng(42), Rng(42));
+        for _ in 0..1000 {
+            let n = a.gen_range(9);
+            assert!(n <= 9);
+            assert_eq!(n, b.gen_range(9));
+        }
+        assert_eq!(Rng(1).gen_range(0), 0);
+        assert!((0..1000).map(|_| a.gen_range(0xffff)).any(|n| n > 0x8000));
+    }
+}
@@ -1,5 +1,10 @@
-use crate::surface::{Surface, Color};
+use crate::surface::{Surface, Color, Palette};
+use crate::crc32;
 use crate::memory::Memory;
+use crate::screenshot::Screenshot;
+use crate::input::Input;
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 pub const SCREEN_WIDTH: usize = 320;
 pub const SCREEN_HEIGHT: usize = 240;
@@ -11,6 +16,9 @@ pub struct Screen<T: Surface> {
     spritew: u8,
     spriteh: u8,
     bg: Color,
+    palette: Palette,
+    hflip: bool,
+    vflip: bool,
     vblank: bool,
     updated: bool,
 }
@@ -23,6 +31,9 @@ impl<T: Surface> Screen<T> {
             spritew: 0,
             spriteh: 0,
             bg: Color::Transparent,
+            palette: Palette::default(),
+            hflip: false,
+            vflip: false,
             vblank: false,
             updated: false,
         }
@@ -45,24 +56,86 @@ impl<T: Surface> Screen<T> {
         self.vblank
     }
 
-    pub fn poll_events(&mut self) {
-        self.surface.poll_events();
+    /// Framebuffer of palette indices, indexed as `buffer[y][x]`.
+    pub fn buffer(&self) -> &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT] {
+        &self.buffer
+    }
+
+    /// CRC32 of the framebuffer, row by row, to compare frames cheaply.
+    pub fn checksum(&self) -> u32 {
+        self.buffer.iter().fold(0, |crc, row| crc32::update(crc, row))
+    }
+
+    /// The frame as shown, through the active palette and with the background
+    /// colour behind transparent pixels.
+    pub fn screenshot(&self) -> Screenshot {
+        Screenshot::new(&self.buffer, self.bg.into(), &self.palette)
+    }
+
+    /// Writes the framebuffer, sprite size, background, palette and flags for a save state.
+    pub(crate) fn save_state<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for row in self.buffer.iter() {
+            writer.write_all(row)?;
+        }
+
+        writer.write_u8(self.spritew)?;
+        writer.write_u8(self.spriteh)?;
+        writer.write_u8(self.bg.into())?;
+        for rgb in self.palette.0.iter() {
+            writer.write_u32::<LittleEndian>(*rgb)?;
+        }
+        writer.write_u8(self.hflip as u8)?;
+        writer.write_u8(self.vflip as u8)?;
+        writer.write_u8(self.vblank as u8)
+    }
+
+    /// Restores a save state and presents it. Nothing changes if the state is truncated.
+    pub(crate) fn load_state<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut buffer = [[0; SCREEN_WIDTH]; SCREEN_HEIGHT];
+        for row in buffer.iter_mut() {
+            reader.read_exact(row)?;
+        }
+
+        let (spritew, spriteh, bg) = (reader.read_u8()?, reader.read_u8()?, reader.read_u8()?);
+        let mut palette = Palette::default();
+        reader.read_u32_into::<LittleEndian>(&mut palette.0)?;
+        let (hflip, vflip, vblank) = (reader.read_u8()?, reader.read_u8()?, reader.read_u8()?);
+
+        self.buffer = buffer;
+        self.spritew = spritew;
+        self.spriteh = spriteh;
+        self.bg = Color::from_u8(bg);
+        self.palette = palette;
+        self.hflip = hflip != 0;
+        self.vflip = vflip != 0;
+        self.vblank = vblank != 0;
+
+        self.surface.cls(self.bg.into(), &self.palette);
+        self.updated = true;
+        self.update_frame();
+
+        Ok(())
+    }
+
+    pub fn poll_events(&mut self, input: &mut Input) {
+        self.surface.poll_events(input);
     }
 
     pub fn update_frame(&mut self) {
         if self.updated {
-            self.surface.present(&self.buffer);
+            self.surface.present(&self.buffer, &self.palette);
             self.updated = false;
         }
-        self.set_vblank();
     }
 
+    /// Clears the framebuffer. Cleared pixels are transparent and show the
+    /// background colour, which the surface paints underneath the buffer.
     pub fn cls(&mut self) {
         for pixel in self.buffer.iter_mut().flat_map(|i| i.iter_mut()) {
-            *pixel = self.bg.into();
+            *pixel = 0;
         }
 
-        self.surface.cls(&self.bg);
+        self.surface.cls(self.bg.into(), &self.palette);
     }
 
     pub fn spr(&mut self, w: u8, h: u8) {
@@ -70,39 +143,72 @@ impl<T: Surface> Screen<T> {
         self.spriteh = h;
     }
 
-    // TODO (alexyer): Implement boundary checks and flips
-    pub fn drw(&mut self, x: i16, y: i16, mut src: u16, mem: &Memory) {
+    /// FLIP: mirror subsequently drawn sprites horizontally and/or vertically.
+    pub fn flip(&mut self, hflip: bool, vflip: bool) {
+        self.hflip = hflip;
+        self.vflip = vflip;
+    }
+
+    /// Draws a sprite, skipping its transparent pixels. The sprite is clipped
+    /// against all four screen edges, so it may be partially visible at any signed position.
+    /// Returns `true` if any opaque sprite pixel was drawn over an opaque framebuffer pixel.
+    pub fn drw(&mut self, x: i16, y: i16, src: u16, mem: &Memory) -> bool {
         self.updated = true;
+        let mut collision = false;
 
-        let mut spritew = self.spritew.wrapping_mul(2) as u16;
-        let mut spriteh = self.spriteh as u16;
+        let (x, y) = (x as i32, y as i32);
+        let spritew = self.spritew as i32 * 2;
+        let spriteh = self.spriteh as i32;
+        let stride = self.spritew as usize;
 
-        if spritew >= SCREEN_WIDTH as u16 {
-            spritew -= 1;
-        }
+        // Offsets into the sprite which land on the screen.
+        let rows = (-y).max(0)..(SCREEN_HEIGHT as i32 - y).min(spriteh);
+        let cols = (-x).max(0)..(SCREEN_WIDTH as i32 - x).min(spritew);
 
-        if spriteh >= SCREEN_HEIGHT as u16 {
-            spriteh -= 1;
-        }
+        for dy in rows {
+            let row = if self.vflip { spriteh - 1 - dy } else { dy } as usize;
+            let j = (y + dy) as usize;
+
+            for dx in cols.clone() {
+                let col = if self.hflip { spritew - 1 - dx } else { dx } as usize;
+                let i = (x + dx) as usize;
 
-        for j in y as usize..(y as u16 + spriteh) as usize {
-            for i in (x as usize..(x as u16 + spritew) as usize).step_by(2) {
-                if i + 1 >= SCREEN_WIDTH || j >= SCREEN_HEIGHT {
+                let byte = mem[(src as usize + row * stride + col / 2) & 0xffff];
+                let pixel = if col & 0x1 == 0 { byte >> 4 } else { byte & 0x0f };
+                if pixel == 0 {
                     continue;
                 }
 
-                self.buffer[j][i + 1] = mem[src as usize] & 0x0f;
-                self.buffer[j][i] = (mem[src as usize] & 0xf0) >> 4;
-                src += 1;
+                collision |= self.buffer[j][i] != 0;
+                self.buffer[j][i] = pixel;
             }
         }
+
+        collision
     }
 
     pub fn bgc(&mut self, n: u8) {
         self.bg = Color::from_u8(n);
-        self.surface.cls(&self.bg);
+        self.surface.cls(self.bg.into(), &self.palette);
         self.update_frame();
     }
+
+    /// Loads a new palette from `Palette::SIZE` bytes of memory at `src`.
+    pub fn pal(&mut self, src: u16, mem: &Memory) {
+        let mut data = [0; Palette::SIZE];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = mem[(src as usize + i) & 0xffff];
+        }
+
+        self.palette.load(&data);
+        self.updated = true;
+    }
+}
+
+impl<T: Surface> Default for Screen<T> {
+    fn default() -> Self {
+        Screen::new()
+    }
 }
 
 #[cfg(test)]
@@ -125,6 +231,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pal() {
+        let mut screen = Screen::<TestSurface>::new();
+        let mut mem = Memory::default();
+        for i in 0..48 {
+            mem[0x1000 + i] = i as u8;
+        }
+
+        screen.pal(0x1000, &mem);
+
+        assert_eq!(screen.palette.0[0], 0x000102);
+        assert_eq!(screen.palette.0[1], 0x030405);
+        assert_eq!(screen.palette.0[15], 0x2d2e2f);
+        assert!(screen.updated);
+    }
+
+    #[test]
+    fn test_checksum_screenshot() {
+        let mut screen = Screen::<TestSurface>::new();
+        let blank = screen.checksum();
+        assert_eq!(blank, crate::crc32::crc32(&[0; SCREEN_WIDTH * SCREEN_HEIGHT]));
+
+        screen.buffer[1][2] = 0xf;
+        screen.bgc(0x2);
+        assert_ne!(screen.checksum(), blank);
+
+        // Transparent pixels show the background.
+        let screenshot = screen.screenshot();
+        assert_eq!(screenshot.rgb(0, 0), screen.palette.0[2]);
+        assert_eq!(screenshot.rgb(2, 1), screen.palette.0[0xf]);
+    }
+
     #[test]
     fn test_drw() {
         let mut screen = Screen::<TestSurface>::new();
@@ -138,10 +276,10 @@ mod tests {
 
         screen.drw(3, 4, 42, &mem);
 
-        for i in 0..SCREEN_WIDTH {
-            for j in 0..SCREEN_HEIGHT {
-                if screen.buffer[i][j] != 0 {
-                    print!("{:X}", screen.buffer[i][j]);
+        for j in 0..SCREEN_HEIGHT {
+            for i in 0..SCREEN_WIDTH {
+                if screen.buffer[j][i] != 0 {
+                    print!("{:X}", screen.buffer[j][i]);
                 } else {
                     print!(".");
                 }
@@ -149,11 +287,127 @@ mod tests {
             print!("\n");
         }
 
-        assert_eq!(screen.buffer[3][4], 0x0b);
+        assert_eq!(screen.buffer[4][3], 0x0b);
         assert_eq!(screen.buffer[4][4], 0x0a);
-        assert_eq!(screen.buffer[3][5], 0x0d);
-        assert_eq!(screen.buffer[4][5], 0x0c);
-        assert_eq!(screen.buffer[3][6], 0x0f);
-        assert_eq!(screen.buffer[4][6], 0x0e);
+        assert_eq!(screen.buffer[5][3], 0x0d);
+        assert_eq!(screen.buffer[5][4], 0x0c);
+        assert_eq!(screen.buffer[6][3], 0x0f);
+        assert_eq!(screen.buffer[6][4], 0x0e);
+    }
+
+    #[test]
+    fn test_drw_flip() {
+        let mut screen = Screen::<TestSurface>::new();
+        screen.spr(2, 2);
+
+        let mut mem = Memory::default();
+        mem[0] = 0x12;
+        mem[1] = 0x34;
+        mem[2] = 0x56;
+        mem[3] = 0x78;
+
+        screen.flip(true, false);
+        screen.drw(0, 0, 0, &mem);
+        assert_eq!(screen.buffer[0][..4], [0x4, 0x3, 0x2, 0x1]);
+        assert_eq!(screen.buffer[1][..4], [0x8, 0x7, 0x6, 0x5]);
+
+        screen.flip(false, true);
+        screen.drw(0, 0, 0, &mem);
+        assert_eq!(screen.buffer[0][..4], [0x5, 0x6, 0x7, 0x8]);
+        assert_eq!(screen.buffer[1][..4], [0x1, 0x2, 0x3, 0x4]);
+
+        screen.flip(true, true);
+        screen.drw(0, 0, 0, &mem);
+        assert_eq!(screen.buffer[0][..4], [0x8, 0x7, 0x6, 0x5]);
+        assert_eq!(screen.buffer[1][..4], [0x4, 0x3, 0x2, 0x1]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_drw_collision() {
+        let mut screen = Screen::<TestSurface>::new();
+        screen.spr(1, 1);
+
+        let mut mem = Memory::default();
+        mem[0] = 0x10;
+        mem[1] = 0x01;
+        mem[2] = 0x02;
+
+        assert!(!screen.drw(0, 0, 0, &mem));
+        assert_eq!(screen.buffer[0][..2], [0x1, 0x0]);
+
+        // Transparent pixels neither collide nor overwrite.
+        assert!(!screen.drw(0, 0, 1, &mem));
+        assert_eq!(screen.buffer[0][..2], [0x1, 0x1]);
+
+        assert!(screen.drw(0, 0, 2, &mem));
+        assert_eq!(screen.buffer[0][..2], [0x1, 0x2]);
+
+        screen.cls();
+        assert!(!screen.drw(0, 0, 2, &mem));
+    }
+
+    #[test]
+    fn test_drw_clip_negative() {
+        let mut screen = Screen::<TestSurface>::new();
+        screen.spr(2, 2);
+
+        let mut mem = Memory::default();
+        mem[0] = 0x12;
+        mem[1] = 0x34;
+        mem[2] = 0x56;
+        mem[3] = 0x78;
+
+        screen.drw(-1, -1, 0, &mem);
+        assert_eq!(screen.buffer[0][..4], [0x6, 0x7, 0x8, 0x0]);
+        assert_eq!(screen.buffer[1][..4], [0x0; 4]);
+
+        screen.cls();
+        screen.flip(true, true);
+        screen.drw(-3, 0, 0, &mem);
+        assert_eq!(screen.buffer[0][..2], [0x5, 0x0]);
+        assert_eq!(screen.buffer[1][..2], [0x1, 0x0]);
+    }
+
+    #[test]
+    fn test_drw_clip_positive() {
+        let mut screen = Screen::<TestSurface>::new();
+        screen.spr(2, 2);
+
+        let mut mem = Memory::default();
+        mem[0] = 0x12;
+        mem[1] = 0x34;
+        mem[2] = 0x56;
+        mem[3] = 0x78;
+
+        screen.drw(SCREEN_WIDTH as i16 - 2, SCREEN_HEIGHT as i16 - 1, 0, &mem);
+        assert_eq!(screen.buffer[SCREEN_HEIGHT - 1][SCREEN_WIDTH - 2..], [0x1, 0x2]);
+        assert_eq!(screen.buffer[SCREEN_HEIGHT - 2][SCREEN_WIDTH - 2..], [0x0, 0x0]);
+
+        // Fully off-screen sprites draw nothing.
+        screen.cls();
+        for (x, y) in [(-4, 0), (0, -2), (SCREEN_WIDTH as i16, 0), (0, SCREEN_HEIGHT as i16), (i16::MIN, i16::MAX)] {
+            assert!(!screen.drw(x, y, 0, &mem));
+        }
+        assert!(screen.buffer.iter().flat_map(|row| row.iter()).all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn test_drw_large_sprite() {
+        let mut screen = Screen::<TestSurface>::new();
+        screen.spr(255, 255);
+
+        let mut mem = Memory::default();
+        for i in 0..255 * 255 {
+            mem[i] = 0x11;
+        }
+
+        screen.drw(-100, -10, 0, &mem);
+        assert!(screen.buffer.iter().flat_map(|row| row.iter()).all(|pixel| *pixel == 0x1));
+
+        screen.cls();
+        screen.drw(300, 230, 0, &mem);
+        assert_eq!(screen.buffer[229][299], 0x0);
+        assert_eq!(screen.buffer[230][300], 0x1);
+        assert_eq!(screen.buffer[SCREEN_HEIGHT - 1][SCREEN_WIDTH - 1], 0x1);
+    }
+}
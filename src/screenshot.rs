@@ -0,0 +1,238 @@
+//! Screenshots of the framebuffer as PNG or PPM images, at native resolution or
+//! scaled up by an integer factor.
+//!
+//! PNGs are written as 4-bit indexed images with the active palette and stored,
+//! uncompressed deflate blocks, which keeps them small without a compressor.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::crc32;
+use crate::error::{Error, Result};
+use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::surface::Palette;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+/// Largest stored deflate block.
+const MAX_BLOCK: usize = 0xffff;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    /// Picks the format from the extension of `path`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?;
+
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+}
+
+/// A frame as palette indices together with the palette it was shown with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Screenshot {
+    /// Palette indices row by row, transparent pixels are replaced by the background.
+    pixels: Vec<u8>,
+    palette: Palette,
+}
+
+impl Screenshot {
+    pub fn new(buffer: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], bg: u8, palette: &Palette) -> Self {
+        Screenshot {
+            pixels: buffer.iter()
+                .flat_map(|row| row.iter())
+                .map(|&i| if i == 0 { bg & 0xf } else { i & 0xf })
+                .collect(),
+            palette: *palette,
+        }
+    }
+
+    /// Colour of the pixel at `x`, `y` as 0xRRGGBB.
+    pub fn rgb(&self, x: usize, y: usize) -> u32 {
+        self.palette.0[self.pixels[y * SCREEN_WIDTH + x] as usize]
+    }
+
+    /// Writes the image in the format picked by the extension of `path`,
+    /// `scale` times the screen size.
+    pub fn save(&self, path: &str, scale: usize) -> Result<()> {
+        let format = ImageFormat::from_path(path).ok_or_else(|| Error::UnknownImageFormat(path.to_string()))?;
+        let writer = BufWriter::new(File::create(path)?);
+
+        match format {
+            ImageFormat::Png => self.write_png(writer, scale),
+            ImageFormat::Ppm => self.write_ppm(writer, scale),
+        }
+    }
+
+    pub fn write_ppm<W: Write>(&self, mut writer: W, scale: usize) -> Result<()> {
+        let scale = scale.max(1);
+        write!(writer, "P6\n{} {}\n255\n", SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale)?;
+
+        for row in self.rows(scale) {
+            for i in row {
+                writer.write_all(&self.palette.0[i as usize].to_be_bytes()[1..])?;
+            }
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_png<W: Write>(&self, mut writer: W, scale: usize) -> Result<()> {
+        let scale = scale.max(1);
+        let (width, height) = (SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale);
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        // Bit depth 4, indexed colour, deflate, no filtering, no interlacing.
+        header.extend_from_slice(&[4, 3, 0, 0, 0]);
+
+        let palette: Vec<u8> = self.palette.0.iter().flat_map(|rgb| rgb.to_be_bytes()[1..].to_vec()).collect();
+
+        // Every row starts with filter type 0 followed by two pixels per byte.
+        let mut data = Vec::with_capacity(height * (1 + width.div_ceil(2)));
+        for row in self.rows(scale) {
+            data.push(0);
+            data.extend(row.chunks(2).map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0)));
+        }
+
+        writer.write_all(&PNG_SIGNATURE)?;
+        write_chunk(&mut writer, b"IHDR", &header)?;
+        write_chunk(&mut writer, b"PLTE", &palette)?;
+        write_chunk(&mut writer, b"IDAT", &zlib_stored(&data))?;
+        write_chunk(&mut writer, b"IEND", &[])?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Rows of palette indices, each pixel and row repeated `scale` times.
+    fn rows(&self, scale: usize) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.pixels.chunks_exact(SCREEN_WIDTH)
+            .flat_map(move |row| {
+                let scaled: Vec<u8> = row.iter().flat_map(|&i| std::iter::repeat_n(i, scale)).collect();
+                std::iter::repeat_n(scaled, scale)
+            })
+    }
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32::update(crc32::crc32(kind), data).to_be_bytes())?;
+    Ok(())
+}
+
+/// Wraps `data` in a zlib stream of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        stream.push(blocks.peek().is_none() as u8);
+        stream.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        stream.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::screen::{SCREEN_HEIGHT, SCREEN_WIDTH};
+    use crate::screenshot::{adler32, zlib_stored, ImageFormat, Screenshot};
+    use crate::surface::Palette;
+
+    fn screenshot() -> Screenshot {
+        let mut buffer = [[0; SCREEN_WIDTH]; SCREEN_HEIGHT];
+        buffer[1][2] = 0xf;
+        buffer[239][319] = 0x3;
+
+        Screenshot::new(&buffer, 0x2, &Palette::default())
+    }
+
+    #[test]
+    fn test_ppm() {
+        let screenshot = screenshot();
+        let palette = Palette::default();
+
+        let mut ppm = Vec::new();
+        screenshot.write_ppm(&mut ppm, 2).unwrap();
+        let header = b"P6\n640 480\n255\n";
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm.len(), header.len() + 640 * 480 * 3);
+
+        // Transparent pixels show the background.
+        let pixel = |x: usize, y: usize| &ppm[header.len() + (y * 640 + x) * 3..][..3];
+        assert_eq!(pixel(0, 0), &palette.0[2].to_be_bytes()[1..]);
+        for (x, y) in [(4, 2), (5, 2), (4, 3), (5, 3)] {
+            assert_eq!(pixel(x, y), &palette.0[0xf].to_be_bytes()[1..]);
+        }
+        assert_eq!(pixel(639, 479), &palette.0[3].to_be_bytes()[1..]);
+        assert_eq!(screenshot.rgb(2, 1), palette.0[0xf]);
+    }
+
+    #[test]
+    fn test_png() {
+        let mut png = Vec::new();
+        screenshot().write_png(&mut png, 1).unwrap();
+
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']);
+        assert_eq!(png[8..16], [0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(png[16..29], [0, 0, 1, 64, 0, 0, 0, 240, 4, 3, 0, 0, 0]);
+        assert_eq!(png[png.len() - 12..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+
+        // 240 rows of a filter byte and 160 bytes of pixels.
+        let idat = 8 + 25 + 12 + 16 * 3;
+        assert_eq!(png[idat + 4..idat + 8], *b"IDAT");
+        let data = &png[idat + 8 + 2 + 5..][..240 * 161];
+        assert_eq!(data[..4], [0, 0x22, 0x22, 0x22]);
+        assert_eq!(data[161..165], [0, 0x22, 0xf2, 0x22]);
+        assert_eq!(data[240 * 161 - 1], 0x23);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 0x01, 0x00, 0x00, 0xff, 0xff, 0, 0, 0, 1]);
+
+        let data = vec![7; 0x10000];
+        let stream = zlib_stored(&data);
+        assert_eq!(stream.len(), 2 + 2 * 5 + data.len() + 4);
+        assert_eq!(stream[2..7], [0x00, 0xff, 0xff, 0x00, 0x00]);
+        assert_eq!(stream[7 + 0xffff..7 + 0xffff + 5], [0x01, 0x01, 0x00, 0xfe, 0xff]);
+    }
+
+    #[test]
+    fn test_image_format() {
+        assert_eq!(ImageFormat::from_path("shot.png"), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path("/tmp/shot.PPM"), Some(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path("shot.bmp"), None);
+        assert_eq!(ImageFormat::from_path("shot"), None);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::audio::AudioSink;
+
+pub const SAMPLE_RATE: u32 = 44100;
+pub const SAMPLES_PER_FRAME: usize = SAMPLE_RATE as usize / 60;
+
+/// Peak amplitude of a tone played at full volume. Leaves some headroom
+/// so the output never clips.
+const AMPLITUDE: f32 = 0x3fff as f32;
+
+/// Attack durations in ms, indexed by the SNG `A` nibble.
+const ATTACK_MS: [u32; 16] = [2, 8, 16, 24, 38, 56, 68, 80, 100, 250, 500, 800, 1000, 3000, 5000, 8000];
+
+/// Decay and release durations in ms, indexed by the SNG `D` and `R` nibbles.
+const DECAY_MS: [u32; 16] = [6, 24, 48, 72, 114, 168, 204, 240, 300, 750, 1500, 2400, 3000, 9000, 15000, 24000];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Waveform {
+    Triangle = 0x0,
+    Sawtooth = 0x1,
+    Pulse = 0x2,
+    Noise = 0x3,
+}
+
+impl Waveform {
+    pub fn from_u8(i: u8) -> Self {
+        match i & 0x3 {
+            0x0 => Waveform::Triangle,
+            0x1 => Waveform::Sawtooth,
+            0x2 => Waveform::Pulse,
+            _ => Waveform::Noise,
+        }
+    }
+}
+
+/// ADSR envelope and waveform set by SNG.
+#[derive(Debug, Copy, Clone)]
+struct Envelope {
+    attack: u32,
+    decay: u32,
+    sustain: f32,
+    release: u32,
+    volume: f32,
+    waveform: Waveform,
+}
+
+/// Tone currently being played. All durations are in samples.
+#[derive(Debug, Copy, Clone)]
+struct Tone {
+    freq: u16,
+    length: u32,
+    elapsed: u32,
+    envelope: Option<Envelope>,
+}
+
+pub struct Sound<T: AudioSink> {
+    sink: T,
+
+    envelope: Envelope,
+    tone: Option<Tone>,
+    phase: f32,
+    noise: u16,
+    samples: Vec<i16>,
+}
+
+impl<T: AudioSink> Sound<T> {
+    pub fn new(sink: T) -> Sound<T> {
+        Sound {
+            sink,
+            envelope: Envelope::default(),
+            tone: None,
+            phase: 0.0,
+            noise: 0xace1,
+            samples: Vec::with_capacity(SAMPLES_PER_FRAME),
+        }
+    }
+
+    pub fn init(&mut self) {
+        self.sink.init();
+    }
+
+    /// SND0: stop playing sound.
+    pub fn stop(&mut self) {
+        self.tone = None;
+    }
+
+    /// SND1, SND2, SND3: play a plain pulse tone for `ms` milliseconds.
+    pub fn tone(&mut self, freq: u16, ms: u16) {
+        self.play(freq, ms, None);
+    }
+
+    /// SNP: play a tone for `ms` milliseconds shaped by the SNG parameters.
+    pub fn snp(&mut self, freq: u16, ms: u16) {
+        self.play(freq, ms, Some(self.envelope));
+    }
+
+    /// SNG: set the sound generation parameters.
+    /// `ad` holds attack and decay, `vt` volume and waveform type, `sr` sustain and release.
+    pub fn sng(&mut self, ad: u8, vt: u8, sr: u8) {
+        self.envelope = Envelope {
+            attack: ms_to_samples(ATTACK_MS[(ad >> 4) as usize]),
+            decay: ms_to_samples(DECAY_MS[(ad & 0x0f) as usize]),
+            sustain: (sr >> 4) as f32 / 15.0,
+            release: ms_to_samples(DECAY_MS[(sr & 0x0f) as usize]),
+            volume: (vt >> 4) as f32 / 15.0,
+            waveform: Waveform::from_u8(vt & 0x0f),
+        };
+    }
+
+    /// Renders one frame worth of samples and hands them over to the sink.
+    pub fn update_frame(&mut self) {
+        self.samples.clear();
+
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = self.next_sample();
+            self.samples.push(sample);
+        }
+
+        self.sink.queue(&self.samples);
+    }
+
+    fn play(&mut self, freq: u16, ms: u16, envelope: Option<Envelope>) {
+        if freq == 0 || ms == 0 {
+            self.stop();
+            return;
+        }
+
+        self.phase = 0.0;
+        self.tone = Some(Tone {
+            freq,
+            length: ms_to_samples(ms as u32),
+            elapsed: 0,
+            envelope,
+        });
+    }
+
+    fn next_sample(&mut self) -> i16 {
+        let tone = match self.tone.as_mut() {
+            Some(tone) => tone,
+            None => return 0,
+        };
+
+        let (level, waveform) = match tone.envelope {
+            Some(envelope) => (envelope.level(tone.elapsed, tone.length), envelope.waveform),
+            None if tone.elapsed < tone.length => (Some(1.0), Waveform::Pulse),
+            None => (None, Waveform::Pulse),
+        };
+
+        let level = match level {
+            Some(level) => level,
+            None => {
+                self.tone = None;
+                return 0;
+            }
+        };
+
+        let freq = tone.freq;
+        tone.elapsed += 1;
+
+        let wave = match waveform {
+            Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+            Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+            Waveform::Pulse => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Noise => if self.noise & 0x1 > 0 { 1.0 } else { -1.0 },
+        };
+
+        self.phase += freq as f32 / SAMPLE_RATE as f32;
+        if self.phase >= 1.0 {
+            self.phase -= self.phase.floor();
+            self.step_noise();
+        }
+
+        (wave * level * AMPLITUDE) as i16
+    }
+
+    /// Advances the 16-bit Galois LFSR used by the noise waveform.
+    fn step_noise(&mut self) {
+        let lsb = self.noise & 0x1;
+        self.noise >>= 1;
+        if lsb > 0 {
+            self.noise ^= 0xb400;
+        }
+    }
+}
+
+impl Envelope {
+    /// Returns the output level for a tone of `length` samples at sample `t`,
+    /// or `None` once the release phase is over.
+    fn level(&self, t: u32, length: u32) -> Option<f32> {
+        if t < length {
+            return Some(self.held_level(t));
+        }
+
+        let released = t - length;
+        if released >= self.release {
+            return None;
+        }
+
+        let start = self.held_level(length);
+        Some(start * (1.0 - released as f32 / self.release as f32))
+    }
+
+    /// Level during the attack, decay and sustain phases.
+    fn held_level(&self, t: u32) -> f32 {
+        if t < self.attack {
+            return self.volume * t as f32 / self.attack as f32;
+        }
+
+        let t = t - self.attack;
+        let sustain = self.volume * self.sustain;
+        if t < self.decay {
+            return self.volume - (self.volume - sustain) * t as f32 / self.decay as f32;
+        }
+
+        sustain
+    }
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope {
+            attack: ms_to_samples(ATTACK_MS[0]),
+            decay: ms_to_samples(DECAY_MS[0]),
+            sustain: 1.0,
+            release: ms_to_samples(DECAY_MS[0]),
+            volume: 1.0,
+            waveform: Waveform::Triangle,
+        }
+    }
+}
+
+#[inline(always)]
+fn ms_to_samples(ms: u32) -> u32 {
+    ms * SAMPLE_RATE / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sound::{Sound, Waveform, SAMPLES_PER_FRAME, ms_to_samples};
+    use crate::audio::NullAudioSink;
+
+    #[test]
+    fn test_silence() {
+        let mut sound = Sound::new(NullAudioSink);
+        sound.update_frame();
+
+        assert_eq!(sound.samples.len(), SAMPLES_PER_FRAME);
+        assert!(sound.samples.iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn test_tone() {
+        let mut sound = Sound::new(NullAudioSink);
+        sound.tone(500, 10);
+        sound.update_frame();
+
+        // 10 ms of sound, silence for the rest of the frame.
+        assert!(sound.samples[..441].iter().all(|s| *s != 0));
+        assert!(sound.samples[441..].iter().all(|s| *s == 0));
+        assert!(sound.tone.is_none());
+    }
+
+    #[test]
+    fn test_stop() {
+        let mut sound = Sound::new(NullAudioSink);
+        sound.tone(1000, 1000);
+        sound.stop();
+        sound.update_frame();
+
+        assert!(sound.samples.iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn test_sng() {
+        let mut sound = Sound::new(NullAudioSink);
+        sound.sng(0x42, 0xf3, 0x81);
+
+        assert_eq!(sound.envelope.attack, ms_to_samples(38));
+        assert_eq!(sound.envelope.decay, ms_to_samples(48));
+        assert_eq!(sound.envelope.release, ms_to_samples(24));
+        assert_eq!(sound.envelope.volume, 1.0);
+        assert_eq!(sound.envelope.waveform, Waveform::Noise);
+    }
+
+    #[test]
+    fn test_snp_envelope() {
+        let mut sound = Sound::new(NullAudioSink);
+        // Attack 8 ms, sawtooth at full volume, sustain at 1/3, release 6 ms.
+        sound.sng(0x10, 0xf1, 0x50);
+        sound.snp(100, 5);
+        let envelope = sound.envelope;
+
+        assert!(envelope.level(0, 220) == Some(0.0));
+        assert!(envelope.level(100, 220).unwrap() < envelope.level(200, 220).unwrap());
+        assert!(envelope.level(300, 220).unwrap() < envelope.level(220, 220).unwrap());
+        assert!(envelope.level(220 + 264, 220).is_none());
+
+        sound.update_frame();
+        let audible = sound.samples.iter().rposition(|s| *s != 0).unwrap();
+        assert!(audible > 220 && audible < 220 + 264);
+    }
+}
@@ -0,0 +1,156 @@
+//! Save states, snapshots of the whole machine in a versioned binary format.
+//!
+//! A state starts with `STATE_MAGIC` and the `STATE_VERSION` byte, followed by
+//! the frame, cycle and frame cycle counters (u64 each), the CPU with its RNG, memory with
+//! the ROM header and the screen including its palette. Numbers are little-endian.
+//! Sound and host input aren't part of the state.
+
+use std::io::{self, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::Rusty16;
+use crate::audio::AudioSink;
+use crate::cpu::Cpu;
+use crate::error::{Error, Result};
+use crate::memory::Memory;
+use crate::surface::Surface;
+
+pub const STATE_MAGIC: [u8; 4] = *b"R16S";
+pub const STATE_VERSION: u8 = 3;
+
+pub(crate) fn save<S: Surface, A: AudioSink, W: Write>(emulator: &Rusty16<S, A>, mut writer: W) -> Result<()> {
+    writer.write_all(&STATE_MAGIC)?;
+    writer.write_u8(STATE_VERSION)?;
+    writer.write_u64::<LittleEndian>(emulator.frame)?;
+    writer.write_u64::<LittleEndian>(emulator.cycles)?;
+    writer.write_u64::<LittleEndian>(emulator.frame_cycles)?;
+
+    emulator.cpu.save_state(&mut writer)?;
+    emulator.memory.save_state(&mut writer)?;
+    emulator.screen.save_state(&mut writer)?;
+
+    Ok(())
+}
+
+/// Restores a state written by `save`. The machine is left untouched if the state is invalid.
+pub(crate) fn load<S: Surface, A: AudioSink, R: Read>(emulator: &mut Rusty16<S, A>, mut reader: R) -> Result<()> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic).map_err(truncated)?;
+    if magic != STATE_MAGIC {
+        return Err(Error::BadState(String::from("Not a save state")));
+    }
+
+    let version = reader.read_u8().map_err(truncated)?;
+    if version != STATE_VERSION {
+        return Err(Error::BadState(format!("Unsupported version {}, expected {}", version, STATE_VERSION)));
+    }
+
+    let mut read_counters = || -> io::Result<[u64; 3]> {
+        Ok([reader.read_u64::<LittleEndian>()?, reader.read_u64::<LittleEndian>()?, reader.read_u64::<LittleEndian>()?])
+    };
+    let [frame, cycles, frame_cycles] = read_counters().map_err(truncated)?;
+
+    let mut cpu = Cpu::default();
+    cpu.load_state(&mut reader).map_err(truncated)?;
+
+    let mut memory = Memory::default();
+    memory.load_state(&mut reader).map_err(|err| match err {
+        Error::Io(err) => truncated(err),
+        err => err,
+    })?;
+
+    // Last, since it modifies the screen once everything is read.
+    emulator.screen.load_state(&mut reader).map_err(truncated)?;
+
+    emulator.frame = frame;
+    emulator.cycles = cycles;
+    emulator.frame_cycles = frame_cycles;
+    emulator.cpu = cpu;
+    emulator.memory = memory;
+
+    Ok(())
+}
+
+fn truncated(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => Error::BadState(String::from("Truncated state")),
+        _ => Error::Io(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Rusty16;
+    use crate::audio::NullAudioSink;
+    use crate::error::Error;
+    use crate::surface::TestSurface;
+
+    const PROGRAM: &[u8] = &[
+        0x04, 0x00, 0x01, 0x01, // SPR 0x0101
+        0x40, 0x00, 0x01, 0x00, // ADDI R0, 1
+        0x07, 0x01, 0xff, 0x00, // RND R1, 0xff
+        0x30, 0x00, 0x00, 0x10, // STM R0, 0x1000
+        0x05, 0x00, 0x00, 0x10, // DRW R0, R0, 0x1000
+        0x02, 0x00, 0x00, 0x00, // VBLNK
+        0x10, 0x00, 0x04, 0x00, // JMP 0x0004
+    ];
+
+    fn emulator() -> Rusty16<'static, TestSurface, NullAudioSink> {
+        let mut emulator = Rusty16::with_audio_sink(NullAudioSink);
+        emulator.rom_data(PROGRAM).init().unwrap();
+        emulator
+    }
+
+    #[test]
+    fn test_save_load() {
+        let mut original = emulator();
+        original.run_frame().unwrap();
+        original.step().unwrap();
+
+        let mut state = Vec::new();
+        original.save_state(&mut state).unwrap();
+
+        // Resumes the random numbers drawn from the original seed.
+        let mut restored = emulator();
+        restored.load_state(state.as_slice()).unwrap();
+        assert_eq!(restored.cpu().seed(), original.cpu().seed());
+
+        for _ in 0..3 {
+            assert_eq!(restored.frame(), original.frame());
+            assert_eq!(restored.cycles(), original.cycles());
+            assert_eq!(restored.cpu().pc(), original.cpu().pc());
+            assert_eq!(restored.cpu().r(), original.cpu().r());
+            assert_eq!(restored.memory()[0..0x10000], original.memory()[0..0x10000]);
+            assert_eq!(restored.screen().buffer()[..], original.screen().buffer()[..]);
+
+            original.run_frame().unwrap();
+            restored.run_frame().unwrap();
+        }
+
+        let mut saved_again = Vec::new();
+        restored.save_state(&mut saved_again).unwrap();
+        let mut expected = Vec::new();
+        original.save_state(&mut expected).unwrap();
+        assert!(saved_again == expected);
+    }
+
+    #[test]
+    fn test_bad_state() {
+        let mut emulator = emulator();
+        emulator.run_frame().unwrap();
+
+        let mut state = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+
+        let mut other = self::emulator();
+        match other.load_state(&state[..state.len() - 1]) {
+            Err(Error::BadState(message)) => assert_eq!(message, "Truncated state"),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(other.cycles(), 0);
+        assert_eq!(other.memory()[0x1000], 0);
+
+        state[4] += 1;
+        assert!(matches!(other.load_state(state.as_slice()), Err(Error::BadState(_))));
+        assert!(matches!(other.load_state(&b"CH16\x01"[..]), Err(Error::BadState(_))));
+    }
+}
@@ -1,20 +1,38 @@
+#[cfg(feature = "sdl")]
 use sdl2::{pixels, EventPump};
-use sdl2::render::{WindowCanvas, Texture, TextureCreator, TextureAccess};
+#[cfg(feature = "sdl")]
+use sdl2::render::{BlendMode, WindowCanvas, Texture, TextureCreator, TextureAccess};
 use crate::screen::{SCREEN_WIDTH, SCREEN_HEIGHT};
+#[cfg(feature = "sdl")]
 use sdl2::pixels::PixelFormatEnum;
+#[cfg(feature = "sdl")]
 use std::cell::RefCell;
-use sdl2::event::EventType;
+#[cfg(feature = "sdl")]
+use sdl2::event::{Event, EventType};
+use crate::input::Input;
+
+#[cfg(feature = "sdl")]
+thread_local! {
+    static SDL_CONTEXT: sdl2::Sdl = sdl2::init().unwrap();
+}
+
+/// SDL can only be initialized once, so the video and audio backends share the context.
+#[cfg(feature = "sdl")]
+pub(crate) fn sdl_context() -> sdl2::Sdl {
+    SDL_CONTEXT.with(|sdl| sdl.clone())
+}
 
 // FIXME: better name
 pub trait Surface {
     fn new() -> Self;
     fn init(&mut self);
-    fn cls(&mut self, bg: &Color);
-    fn poll_events(&mut self);
-    fn present(&mut self, new_buffer: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]);
+    fn cls(&mut self, bg: u8, palette: &Palette);
+    fn poll_events(&mut self, input: &mut Input);
+    fn present(&mut self, new_buffer: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], palette: &Palette);
 }
 
 pub struct TestSurface;
+#[cfg(feature = "sdl")]
 pub struct SdlSurface{
     canvas: WindowCanvas,
     events: EventPump,
@@ -26,17 +44,18 @@ impl Surface for TestSurface {
         TestSurface {}
     }
     fn init(&mut self) {}
-    fn cls(&mut self, _bg: &Color) {}
-    fn poll_events(&mut self) {}
-    fn present(&mut self, _new_buffer: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]) {}
+    fn cls(&mut self, _bg: u8, _palette: &Palette) {}
+    fn poll_events(&mut self, _input: &mut Input) {}
+    fn present(&mut self, _new_buffer: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], _palette: &Palette) {}
 }
 
+#[cfg(feature = "sdl")]
 impl Surface for SdlSurface {
     fn new() -> Self {
         let width = SCREEN_WIDTH as u32;
         let height = SCREEN_HEIGHT as u32;
 
-        let sdl_context = sdl2::init().unwrap();
+        let sdl_context = sdl_context();
         let video_subsys = sdl_context.video().unwrap();
         let window = video_subsys
             .window(
@@ -62,8 +81,10 @@ impl Surface for SdlSurface {
         events.disable_event(EventType::MouseMotion);
 
         let creator = canvas.texture_creator();
-        let texture = creator.create_texture(
+        let mut texture = creator.create_texture(
             PixelFormatEnum::ARGB8888, TextureAccess::Streaming, width, height).unwrap();
+        // Let the background colour show through transparent pixels.
+        texture.set_blend_mode(BlendMode::Blend);
 
         let texture = unsafe{
             std::mem::transmute::<_,Texture<'static>>(texture)
@@ -83,27 +104,30 @@ impl Surface for SdlSurface {
         self.canvas.present();
     }
 
-    fn cls(&mut self, bg: &Color) {
-        let (r, g, b, a) = bg.to_tuple();
+    fn cls(&mut self, bg: u8, palette: &Palette) {
+        let (r, g, b, a) = palette.to_tuple(bg);
         self.canvas.set_draw_color(pixels::Color::RGBA(r, g, b, a));
         self.canvas.clear();
         self.canvas.present();
     }
 
-    fn poll_events(&mut self) {
+    fn poll_events(&mut self, input: &mut Input) {
         for event in self.events.poll_iter() {
             match event {
+                Event::Quit { .. } => input.set_quit(),
+                Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => input.key_down(&keycode.name()),
+                Event::KeyUp { keycode: Some(keycode), .. } => input.key_up(&keycode.name()),
                 _ => ()
             }
         }
     }
 
-    fn present(&mut self, new_buffer: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT]) {
+    fn present(&mut self, new_buffer: &[[u8; SCREEN_WIDTH]; SCREEN_HEIGHT], palette: &Palette) {
         let mut texture = self.texture.borrow_mut();
         texture.with_lock(None, |buffer, pitch| {
            for j in 0..SCREEN_HEIGHT {
                for i in 0..SCREEN_WIDTH {
-                   let (r, g, b, a) = Color::from_u8(new_buffer[j][i]).to_tuple();
+                   let (r, g, b, a) = palette.to_tuple(new_buffer[j][i]);
                    let offset = j * pitch + i * 4;
                    buffer[offset] = b;
                    buffer[offset + 1] = g;
@@ -119,7 +143,7 @@ impl Surface for SdlSurface {
     }
 }
 
-// TODO (alexyer): Support dynamic palette
+/// Colour indices with their power-on RGB values. PAL may remap them at runtime.
 #[derive(Copy, Clone)]
 pub enum Color {
     Transparent = 0x0,
@@ -164,28 +188,6 @@ impl Color {
         }
     }
 
-    pub fn argb(&self) -> u32 {
-        match self {
-            Color::Transparent | Color::Unknown => 0x0,
-            _ => (0xff << 24) | self.rgb(),
-        }
-    }
-
-    pub fn to_tuple(&self) -> (u8, u8, u8, u8) {
-        let rgb = self.rgb();
-
-        let r = ((rgb & 0xff0000) >> 16) as u8;
-        let g = ((rgb & 0x00ff00) >> 8) as u8;
-        let b = (rgb & 0x0000ff) as u8;
-
-        let a = match self {
-            Color::Transparent => 0,
-            _ => 0xff
-        };
-
-        (r, g, b, a)
-    }
-
     pub fn from_u8(i: u8) -> Self {
         match i {
             0x0 => Color::Transparent,
@@ -232,3 +234,70 @@ impl Into<u8> for Color {
         }
     }
 }
+
+/// 16-colour palette, each entry holds a 0xRRGGBB value.
+/// Index 0 is always rendered transparent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Palette(pub [u32; 16]);
+
+impl Palette {
+    pub const SIZE: usize = 16 * 3;
+
+    /// Loads the palette from `Palette::SIZE` bytes of RR GG BB triplets.
+    pub fn load(&mut self, data: &[u8]) {
+        for (entry, rgb) in self.0.iter_mut().zip(data.chunks_exact(3)) {
+            *entry = ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32;
+        }
+    }
+
+    pub fn argb(&self, i: u8) -> u32 {
+        match i {
+            0x0 => 0x0,
+            0x1..=0xf => (0xff << 24) | self.0[i as usize],
+            _ => 0x0,
+        }
+    }
+
+    pub fn to_tuple(&self, i: u8) -> (u8, u8, u8, u8) {
+        let argb = self.argb(i);
+
+        let a = ((argb & 0xff000000) >> 24) as u8;
+        let r = ((argb & 0x00ff0000) >> 16) as u8;
+        let g = ((argb & 0x0000ff00) >> 8) as u8;
+        let b = (argb & 0x000000ff) as u8;
+
+        (r, g, b, a)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let mut palette = [0; 16];
+        for (i, entry) in palette.iter_mut().enumerate() {
+            *entry = Color::from_u8(i as u8).rgb();
+        }
+
+        Palette(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::surface::Palette;
+
+    #[test]
+    fn test_palette_load() {
+        let mut palette = Palette::default();
+        assert_eq!(palette.to_tuple(0x3), (0xbf, 0x39, 0x32, 0xff));
+
+        let mut data = [0u8; Palette::SIZE];
+        data[3..6].copy_from_slice(&[0x12, 0x34, 0x56]);
+        data[45..48].copy_from_slice(&[0xde, 0xad, 0xbe]);
+        palette.load(&data);
+
+        assert_eq!(palette.argb(0x0), 0x0);
+        assert_eq!(palette.argb(0x1), 0xff123456);
+        assert_eq!(palette.argb(0x3), 0xff000000);
+        assert_eq!(palette.to_tuple(0xf), (0xde, 0xad, 0xbe, 0xff));
+    }
+}
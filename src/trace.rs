@@ -0,0 +1,532 @@
+//! Execution traces, one record per executed instruction with the machine state before it ran.
+//!
+//! Both formats start with a header holding the seed of the RND instruction at the
+//! first traced instruction, replaying a trace needs the same seed.
+//!
+//! The text format starts with a `#` comment line followed by one line per instruction:
+//!
+//! ```text
+//! CYCLE      PC   BYTES        MNEMONIC             SP      FLAGS R0 ... RF
+//! 0000000042 0104 20 01 07 00  LDI R1, 0x0007       SP=FDF2 F=04 R=0005 0000 ... 0000
+//! ```
+//!
+//! The binary format starts with `TRACE_MAGIC`, the `TRACE_VERSION` byte and the seed (u64)
+//! followed by `RECORD_SIZE` byte records of little-endian fields: cycle (u64), PC (u16), SP (u16),
+//! the instruction bytes, flags (u8), a reserved zero byte and R0-RF (i16 each).
+//! Mnemonics aren't stored, they follow from the instruction bytes.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use crate::cpu::{Cpu, INSTRUCTION_SIZE};
+use crate::error::{Error, Result};
+use crate::instruction::Instruction;
+use crate::memory::Memory;
+
+pub const TRACE_MAGIC: [u8; 4] = *b"R16T";
+pub const TRACE_VERSION: u8 = 2;
+/// Size of a record in the binary format.
+pub const RECORD_SIZE: usize = 50;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("Unknown trace format: {}, expected text or binary", s)),
+        }
+    }
+}
+
+/// Instruction groups, one per high nibble of the opcode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OpcodeClass {
+    Misc,
+    Jump,
+    Load,
+    Store,
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Mul,
+    Div,
+    Shift,
+    Stack,
+    Palette,
+    Not,
+}
+
+const OPCODE_CLASSES: [(OpcodeClass, &str); 15] = [
+    (OpcodeClass::Misc, "misc"),
+    (OpcodeClass::Jump, "jump"),
+    (OpcodeClass::Load, "load"),
+    (OpcodeClass::Store, "store"),
+    (OpcodeClass::Add, "add"),
+    (OpcodeClass::Sub, "sub"),
+    (OpcodeClass::And, "and"),
+    (OpcodeClass::Or, "or"),
+    (OpcodeClass::Xor, "xor"),
+    (OpcodeClass::Mul, "mul"),
+    (OpcodeClass::Div, "div"),
+    (OpcodeClass::Shift, "shift"),
+    (OpcodeClass::Stack, "stack"),
+    (OpcodeClass::Palette, "palette"),
+    (OpcodeClass::Not, "not"),
+];
+
+impl OpcodeClass {
+    pub fn of(opcode: u8) -> Option<Self> {
+        OPCODE_CLASSES.get((opcode >> 4) as usize).map(|(class, _)| *class)
+    }
+}
+
+impl FromStr for OpcodeClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        OPCODE_CLASSES.iter()
+            .find(|(_, name)| *name == s)
+            .map(|(class, _)| *class)
+            .ok_or_else(|| format!("Unknown opcode class: {}", s))
+    }
+}
+
+impl fmt::Display for OpcodeClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", OPCODE_CLASSES[*self as usize].1)
+    }
+}
+
+/// Machine state before an instruction is executed.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub bytes: [u8; INSTRUCTION_SIZE],
+    pub sp: u16,
+    pub flags: u8,
+    pub r: [i16; 16],
+}
+
+impl TraceRecord {
+    pub fn new(cycle: u64, cpu: &Cpu, memory: &Memory) -> Self {
+        TraceRecord {
+            cycle,
+            pc: cpu.pc(),
+            bytes: memory.instruction_bytes(cpu.pc()),
+            sp: cpu.sp(),
+            flags: cpu.flags().0,
+            r: *cpu.r(),
+        }
+    }
+
+    pub fn write_text<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "{}", self)
+    }
+
+    pub fn write_binary<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_u64::<LittleEndian>(self.cycle)?;
+        writer.write_u16::<LittleEndian>(self.pc)?;
+        writer.write_u16::<LittleEndian>(self.sp)?;
+        writer.write_all(&self.bytes)?;
+        writer.write_u8(self.flags)?;
+        writer.write_u8(0)?;
+        for r in self.r.iter() {
+            writer.write_i16::<LittleEndian>(*r)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_binary<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut record = TraceRecord {
+            cycle: reader.read_u64::<LittleEndian>()?,
+            pc: reader.read_u16::<LittleEndian>()?,
+            sp: reader.read_u16::<LittleEndian>()?,
+            ..Default::default()
+        };
+
+        reader.read_exact(&mut record.bytes)?;
+        record.flags = reader.read_u8()?;
+        reader.read_u8()?;
+        reader.read_i16_into::<LittleEndian>(&mut record.r)?;
+
+        Ok(record)
+    }
+}
+
+/// Parses a line of the text format.
+impl FromStr for TraceRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let malformed = || format!("Malformed trace record: {}", s);
+        let hex = |text: Option<&str>| text.and_then(|text| u16::from_str_radix(text, 16).ok()).ok_or_else(malformed);
+
+        // The mnemonic between the instruction bytes and SP can contain spaces.
+        let (head, state) = s.rsplit_once(" SP=").ok_or_else(malformed)?;
+        let mut head = head.split_whitespace();
+        let mut state = state.split_whitespace();
+
+        let mut record = TraceRecord {
+            cycle: head.next().and_then(|cycle| cycle.parse().ok()).ok_or_else(malformed)?,
+            pc: hex(head.next())?,
+            ..Default::default()
+        };
+
+        for byte in record.bytes.iter_mut() {
+            *byte = hex(head.next())? as u8;
+        }
+
+        record.sp = hex(state.next())?;
+        record.flags = hex(state.next().and_then(|flags| flags.strip_prefix("F=")))? as u8;
+
+        let mut r = state.next().and_then(|r| r.strip_prefix("R="));
+        for reg in record.r.iter_mut() {
+            *reg = hex(r)? as i16;
+            r = state.next();
+        }
+
+        match r {
+            Some(_) => Err(malformed()),
+            None => Ok(record),
+        }
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.bytes;
+        write!(f, "{:010} {:04X} {:02X} {:02X} {:02X} {:02X}  {:<20} SP={:04X} F={:02X} R=",
+               self.cycle, self.pc, b[0], b[1], b[2], b[3], Instruction(b).to_asm_str(), self.sp, self.flags)?;
+
+        for (i, r) in self.r.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            write!(f, "{}{:04X}", separator, *r as u16)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the records of a trace in either format.
+pub struct TraceReader {
+    reader: Box<dyn BufRead>,
+    format: TraceFormat,
+    seed: u64,
+    /// Line number of the last text record.
+    line: usize,
+}
+
+impl TraceReader {
+    /// Detects the format and checks the version from the header.
+    pub fn new<R: Read + 'static>(reader: R) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+
+        let (format, seed) = if reader.fill_buf()?.starts_with(&TRACE_MAGIC) {
+            let mut header = [0; 5];
+            reader.read_exact(&mut header)?;
+            check_version(header[4])?;
+
+            let seed = reader.read_u64::<LittleEndian>()
+                .map_err(|_| Error::BadTrace(String::from("Truncated header")))?;
+
+            (TraceFormat::Binary, seed)
+        } else {
+            let mut header = String::new();
+            reader.read_line(&mut header)?;
+
+            let missing = || Error::BadTrace(String::from("Missing header"));
+            let mut fields = header.trim_end().strip_prefix("# rusty16 trace version ")
+                .ok_or_else(missing)?
+                .split(' ');
+
+            check_version(fields.next().and_then(|version| version.parse().ok()).ok_or_else(missing)?)?;
+
+            let seed = match (fields.next(), fields.next()) {
+                (Some("seed"), Some(seed)) => seed.strip_prefix("0x").and_then(|hex| u64::from_str_radix(hex, 16).ok()),
+                _ => None,
+            };
+
+            (TraceFormat::Text, seed.ok_or_else(missing)?)
+        };
+
+        Ok(TraceReader {
+            reader: Box::new(reader),
+            format,
+            seed,
+            line: 1,
+        })
+    }
+
+    pub fn open(path: &str) -> Result<Self> {
+        TraceReader::new(File::open(path)?)
+    }
+
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Seed of the RND instruction in the traced run.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn read_text(&mut self) -> Result<Option<TraceRecord>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+
+        line.trim_end().parse()
+            .map(Some)
+            .map_err(|err| Error::BadTrace(format!("line {}: {}", self.line, err)))
+    }
+
+    fn read_binary(&mut self) -> Result<Option<TraceRecord>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        match TraceRecord::read_binary(&mut self.reader) {
+            Ok(record) => Ok(Some(record)),
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Err(Error::BadTrace(String::from("Truncated record"))),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = Result<TraceRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.format {
+            TraceFormat::Text => self.read_text(),
+            TraceFormat::Binary => self.read_binary(),
+        };
+
+        record.transpose()
+    }
+}
+
+fn check_version(version: u8) -> Result<()> {
+    if version != TRACE_VERSION {
+        return Err(Error::BadTrace(format!("Unsupported version {}, expected {}", version, TRACE_VERSION)));
+    }
+
+    Ok(())
+}
+
+/// Writes a `TraceRecord` for every executed instruction which passes the filters.
+/// By default everything is traced.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    /// Whether the header has been written.
+    started: bool,
+
+    addresses: RangeInclusive<u16>,
+    classes: BTreeSet<OpcodeClass>,
+    frames: RangeInclusive<u64>,
+}
+
+impl Tracer {
+    /// Starts a trace on `writer`. The header is written with the first
+    /// instruction, once the seed is known.
+    pub fn new<W: Write + 'static>(writer: W, format: TraceFormat) -> Self {
+        Tracer {
+            writer: Box::new(writer),
+            format,
+            started: false,
+            addresses: 0..=0xffff,
+            classes: BTreeSet::new(),
+            frames: 0..=u64::MAX,
+        }
+    }
+
+    /// Starts a buffered trace into the file at `path`.
+    pub fn create(path: &str, format: TraceFormat) -> Result<Self> {
+        Ok(Tracer::new(BufWriter::new(File::create(path)?), format))
+    }
+
+    /// Only traces instructions at PC `first` to `last`, inclusive.
+    pub fn address_range(&mut self, first: u16, last: u16) -> &mut Self {
+        self.addresses = first..=last;
+        self
+    }
+
+    /// Only traces instructions of the given classes, can be called repeatedly.
+    pub fn opcode_class(&mut self, class: OpcodeClass) -> &mut Self {
+        self.classes.insert(class);
+        self
+    }
+
+    /// Only traces frames `first` to `last`, inclusive.
+    pub fn frames(&mut self, first: u64, last: u64) -> &mut Self {
+        self.frames = first..=last;
+        self
+    }
+
+    /// Records the instruction at the PC, which is about to be executed in `frame`.
+    pub fn trace(&mut self, frame: u64, cycle: u64, cpu: &Cpu, memory: &Memory) -> Result<()> {
+        if !self.started {
+            self.write_header(cpu.seed())?;
+        }
+
+        if !self.frames.contains(&frame) || !self.addresses.contains(&cpu.pc()) {
+            return Ok(());
+        }
+
+        let record = TraceRecord::new(cycle, cpu, memory);
+        if !self.classes.is_empty() && !OpcodeClass::of(record.bytes[0]).is_some_and(|class| self.classes.contains(&class)) {
+            return Ok(());
+        }
+
+        match self.format {
+            TraceFormat::Text => record.write_text(&mut self.writer)?,
+            TraceFormat::Binary => record.write_binary(&mut self.writer)?,
+        }
+
+        Ok(())
+    }
+
+    fn write_header(&mut self, seed: u64) -> Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "# rusty16 trace version {} seed {:#018x}", TRACE_VERSION, seed)?,
+            TraceFormat::Binary => {
+                self.writer.write_all(&TRACE_MAGIC)?;
+                self.writer.write_u8(TRACE_VERSION)?;
+                self.writer.write_u64::<LittleEndian>(seed)?;
+            },
+        }
+
+        self.started = true;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use crate::cpu::Cpu;
+    use crate::memory::Memory;
+    use crate::trace::{OpcodeClass, TraceFormat, TraceReader, TraceRecord, Tracer, RECORD_SIZE};
+
+    #[test]
+    fn test_record() {
+        let mut memory = Memory::default();
+        memory.load_rom_bytes(&[0x00, 0x00, 0x00, 0x00, 0x20, 0x01, 0x07, 0x00]).unwrap();
+
+        let mut cpu = Cpu::default();
+        cpu.set_pc(4);
+        cpu.set_r(0, 5);
+        cpu.set_r(15, -1);
+
+        let record = TraceRecord::new(42, &cpu, &memory);
+        assert_eq!(record.to_string(),
+                   "0000000042 0004 20 01 07 00  LDI R1, 0x0007       SP=FDF0 F=00 \
+                    R=0005 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 FFFF");
+
+        let mut binary = Vec::new();
+        record.write_binary(&mut binary).unwrap();
+        assert_eq!(binary.len(), RECORD_SIZE);
+        assert_eq!(binary[..16], [42, 0, 0, 0, 0, 0, 0, 0, 0x04, 0x00, 0xf0, 0xfd, 0x20, 0x01, 0x07, 0x00]);
+        assert_eq!(binary[RECORD_SIZE - 2..], [0xff, 0xff]);
+
+        assert_eq!(TraceRecord::read_binary(&mut binary.as_slice()).unwrap(), record);
+        assert_eq!(record.to_string().parse(), Ok(record));
+        assert!(TraceRecord::from_str("0000000042 0004 20 01 07 00  LDI R1, 0x0007       SP=FDF0 F=00 R=0005").is_err());
+    }
+
+    #[test]
+    fn test_reader() {
+        let mut cpu = Cpu::default();
+        cpu.set_seed(0x1234);
+        let memory = Memory::default();
+        let records: Vec<TraceRecord> = (0..3).map(|cycle| TraceRecord::new(cycle, &cpu, &memory)).collect();
+
+        for format in [TraceFormat::Text, TraceFormat::Binary] {
+            let path = std::env::temp_dir().join(format!("rusty16_test_reader_{:?}.trace", format));
+            let mut tracer = Tracer::create(path.to_str().unwrap(), format).unwrap();
+            for cycle in 0..3 {
+                tracer.trace(0, cycle, &cpu, &memory).unwrap();
+            }
+            tracer.flush().unwrap();
+
+            let reader = TraceReader::open(path.to_str().unwrap()).unwrap();
+            assert_eq!(reader.format(), format);
+            assert_eq!(reader.seed(), 0x1234);
+            assert_eq!(reader.collect::<Result<Vec<_>, _>>().unwrap(), records);
+
+            // A truncated last record.
+            let mut trace = std::fs::read(&path).unwrap();
+            trace.truncate(trace.len() - 8);
+            std::fs::remove_file(&path).unwrap();
+            assert!(TraceReader::new(std::io::Cursor::new(trace)).unwrap().nth(2).unwrap().is_err());
+        }
+
+        assert!(TraceReader::new(&b"R16T\x01"[..]).is_err());
+        assert!(TraceReader::new(&b"R16T\x02\x00"[..]).is_err());
+        assert!(TraceReader::new(&b"# rusty16 trace version 1\n"[..]).is_err());
+        assert!(TraceReader::new(&b"# rusty16 trace version 2 seed 12\n"[..]).is_err());
+        assert!(TraceReader::new(&b"0000000000 0000"[..]).is_err());
+    }
+
+    #[test]
+    fn test_filters() {
+        let mut memory = Memory::default();
+        memory.load_rom_bytes(&[0x20, 0x01, 0x07, 0x00, 0x10, 0x00, 0x00, 0x00]).unwrap();
+        let mut cpu = Cpu::default();
+
+        let path = std::env::temp_dir().join("rusty16_test_filters.trace");
+        let mut tracer = Tracer::create(path.to_str().unwrap(), TraceFormat::Binary).unwrap();
+        tracer.address_range(0, 0xff).opcode_class(OpcodeClass::Jump).frames(1, 2);
+
+        for frame in 0..4 {
+            for pc in [0, 4, 0x100] {
+                cpu.set_pc(pc);
+                tracer.trace(frame, frame, &cpu, &memory).unwrap();
+            }
+        }
+        tracer.flush().unwrap();
+
+        let trace = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Only the JMP at 0x0004 in frames 1 and 2, after the header with a zero seed.
+        assert_eq!(&trace[..13], b"R16T\x02\0\0\0\0\0\0\0\0");
+        assert_eq!(trace.len(), 13 + 2 * RECORD_SIZE);
+        assert_eq!(trace[13], 1);
+        assert_eq!(trace[13 + RECORD_SIZE], 2);
+    }
+
+    #[test]
+    fn test_opcode_class() {
+        assert_eq!(OpcodeClass::of(0x10), Some(OpcodeClass::Jump));
+        assert_eq!(OpcodeClass::of(0xe5), Some(OpcodeClass::Not));
+        assert_eq!(OpcodeClass::of(0xf0), None);
+        assert_eq!(OpcodeClass::from_str("shift"), Ok(OpcodeClass::Shift));
+        assert_eq!(OpcodeClass::Palette.to_string(), "palette");
+        assert!(OpcodeClass::from_str("jmp").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+extern crate rusty16;
+
+use std::{env, fs, process};
+use rusty16::audio::NullAudioSink;
+use rusty16::diff::{diff, lockstep, Divergence};
+use rusty16::surface::TestSurface;
+use rusty16::trace::TraceReader;
+use rusty16::{Result, Rusty16, CPU_FREQUENCY};
+
+const USAGE: &str = "Usage: rusty16-tracediff [OPTIONS] LEFT RIGHT
+
+Reports the first instruction where two traces written with rusty16 --trace diverge.
+
+Options:
+    -C, --context N     Records shown around the divergence, defaults to 5
+    --lockstep          LEFT and RIGHT are ROMs, run them side by side and also compare memory writes
+    --cycles N          Instructions to run in lockstep, defaults to a minute of emulated time
+    --seed N            Seed of the RND instruction of both lockstep runs, defaults to 0
+
+Exits with 0 if the runs match, 1 if they diverge and 2 on errors.";
+
+struct Options {
+    left: String,
+    right: String,
+    context: usize,
+    lockstep: bool,
+    cycles: u64,
+    seed: u64,
+}
+
+impl Options {
+    fn parse() -> Options {
+        let mut files = Vec::new();
+        let mut context = 5;
+        let mut lockstep = false;
+        let mut cycles = 60 * CPU_FREQUENCY;
+        let mut seed = 0;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-C" | "--context" => {
+                    let n = args.next().unwrap_or_else(|| usage(&format!("{} requires a number", arg)));
+                    context = n.parse().unwrap_or_else(|_| usage(&format!("Invalid number: {}", n)));
+                },
+                "--lockstep" => lockstep = true,
+                "--cycles" => {
+                    let n = args.next().unwrap_or_else(|| usage("--cycles requires a number"));
+                    cycles = n.parse().unwrap_or_else(|_| usage(&format!("Invalid number: {}", n)));
+                },
+                "--seed" => {
+                    let n = args.next().unwrap_or_else(|| usage("--seed requires a number"));
+                    seed = n.parse().unwrap_or_else(|_| usage(&format!("Invalid number: {}", n)));
+                },
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                },
+                _ if arg.starts_with('-') => usage(&format!("Unknown option: {}", arg)),
+                _ => files.push(arg),
+            }
+        }
+
+        if files.len() != 2 {
+            usage("Expected two files");
+        }
+
+        Options {
+            right: files.pop().unwrap(),
+            left: files.pop().unwrap(),
+            context,
+            lockstep,
+            cycles,
+            seed,
+        }
+    }
+}
+
+fn usage(err: &str) -> ! {
+    eprintln!("{}\n\n{}", err, USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    let options = Options::parse();
+
+    let result = if options.lockstep {
+        run_lockstep(&options)
+    } else {
+        TraceReader::open(&options.left)
+            .and_then(|left| Ok((left, TraceReader::open(&options.right)?)))
+            .and_then(|(left, right)| {
+                if left.seed() != right.seed() {
+                    println!("Seeds differ: {:#x} != {:#x}, RND results will too", left.seed(), right.seed());
+                }
+
+                diff(left, right, options.context)
+            })
+    };
+
+    match result {
+        Ok(Some(divergence)) => {
+            print!("{}", divergence);
+            process::exit(1);
+        },
+        Ok(None) => println!("No divergence"),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        },
+    }
+}
+
+fn run_lockstep(options: &Options) -> Result<Option<Divergence>> {
+    let (left_rom, right_rom) = (fs::read(&options.left)?, fs::read(&options.right)?);
+
+    let mut left = Rusty16::<TestSurface, _>::with_audio_sink(NullAudioSink);
+    let mut right = Rusty16::<TestSurface, _>::with_audio_sink(NullAudioSink);
+    left.rom_data(&left_rom).seed(options.seed).init()?;
+    right.rom_data(&right_rom).seed(options.seed).init()?;
+
+    lockstep(&mut left, &mut right, options.cycles, options.context)
+}